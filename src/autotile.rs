@@ -0,0 +1,215 @@
+use std::collections::VecDeque;
+use grid_2d::{Coord, Grid};
+use crate::TileLabel;
+use crate::tileset::{Tileset, Direction, Transform};
+
+// The tiles that make up a body of water, with the plain tile first.
+pub const WATER: &[&str] = &["water", "water_edge", "water_corner", "water_inner_corner"];
+
+// Picks which variant and rotation of a family of tiles each cell in a mask needs, so that every
+// tile satisfies the tileset's neighbour rules. Everything outside the mask is left alone, and
+// constrains what can be placed next to it.
+pub struct Autotiler<'a> {
+	pub tileset: &'a Tileset,
+	pub family: &'a [&'a str],
+}
+
+pub struct Autotiled {
+	pub labels: Grid<TileLabel>,
+	// Cells that no variant fits, which are left as the plain tile.
+	pub unresolved: Vec<Coord>,
+}
+
+impl<'a> Autotiler<'a> {
+	pub fn water(tileset: &'a Tileset) -> Self {
+		Self { tileset, family: WATER }
+	}
+
+	pub fn is_member(&self, label: &TileLabel) -> bool {
+		self.family.contains(&&label.label[..])
+	}
+
+	fn candidates(&self) -> Vec<TileLabel> {
+		let mut candidates: Vec<TileLabel> = Vec::new();
+
+		for label in self.family {
+			let label = TileLabel::new(label);
+
+			for transform in Transform::all() {
+				let candidate = transform.label(&label);
+
+				// Skip transforms that the tile's symmetry makes identical.
+				if !candidates.iter().any(|other| self.tileset.same_tile(other, &candidate)) {
+					candidates.push(candidate);
+				}
+			}
+		}
+
+		candidates
+	}
+
+	fn fits(&self, tile: &TileLabel, direction: Direction, neighbour: &TileLabel) -> bool {
+		self.tileset.allows(tile, direction, neighbour) || self.tileset.allows(neighbour, direction.opposite(), tile)
+	}
+
+	pub fn apply(&self, terrain: &Grid<TileLabel>, mask: &Grid<bool>) -> Autotiled {
+		let candidates = self.candidates();
+
+		let fixed = Grid::new_fn(terrain.size(), |position| match mask.get_checked(position) {
+			true => None,
+			false => Some(terrain.get_checked(position).clone()),
+		});
+
+		// `None` outside the mask, or once a cell has turned out to be impossible, so that it's left
+		// out of propagation.
+		let mut domains: Grid<Option<Vec<TileLabel>>> = Grid::new_fn(terrain.size(), |position| {
+			if fixed.get_checked(position).is_some() {
+				return None;
+			}
+
+			Some(candidates.iter()
+				.filter(|candidate| Direction::ALL.iter().all(|&direction| {
+					match fixed.get(position + direction.offset()) {
+						Some(Some(neighbour)) => self.fits(candidate, direction, neighbour),
+						_ => true,
+					}
+				}))
+				.cloned()
+				.collect())
+		});
+
+		let masked: Vec<Coord> = mask.enumerate().filter(|(_, masked)| **masked).map(|(position, _)| position).collect();
+		let mut unresolved = Vec::new();
+
+		// Cells that nothing fits next to what's already around them are impossible from the start.
+		for &position in &masked {
+			if domains.get_checked(position).as_ref().is_some_and(|domain| domain.is_empty()) {
+				unresolved.push(position);
+				*domains.get_checked_mut(position) = None;
+			}
+		}
+
+		self.propagate(&mut domains, masked.iter().cloned().collect(), &mut unresolved);
+
+		// Settle each cell in turn, keeping what's already there when it still fits.
+		for &position in &masked {
+			let choice = match domains.get_checked(position) {
+				Some(domain) if domain.len() > 1 => {
+					let existing = terrain.get_checked(position);
+					domain.iter()
+						.find(|candidate| self.tileset.same_tile(candidate, existing))
+						.unwrap_or(&domain[0])
+						.clone()
+				},
+				_ => continue,
+			};
+
+			*domains.get_checked_mut(position) = Some(vec![choice]);
+			self.propagate(&mut domains, vec![position].into(), &mut unresolved);
+		}
+
+		let labels = Grid::new_fn(terrain.size(), |position| {
+			match (fixed.get_checked(position), domains.get_checked(position)) {
+				(Some(label), _) => label.clone(),
+				(None, Some(domain)) if !domain.is_empty() => domain[0].clone(),
+				_ => TileLabel::new(self.family[0]),
+			}
+		});
+
+		Autotiled { labels, unresolved }
+	}
+
+	// Removes candidates that nothing next to them fits with, until nothing changes.
+	fn propagate(&self, domains: &mut Grid<Option<Vec<TileLabel>>>, mut queue: VecDeque<Coord>, unresolved: &mut Vec<Coord>) {
+		while let Some(position) = queue.pop_front() {
+			for &direction in &Direction::ALL {
+				let neighbour = position + direction.offset();
+
+				let (domain, neighbour_domain) = match (domains.get(position), domains.get(neighbour)) {
+					(Some(Some(domain)), Some(Some(neighbour_domain))) => (domain, neighbour_domain),
+					_ => continue,
+				};
+
+				let remaining: Vec<TileLabel> = neighbour_domain.iter()
+					.filter(|candidate| domain.iter().any(|tile| self.fits(tile, direction, candidate)))
+					.cloned()
+					.collect();
+
+				if remaining.len() == neighbour_domain.len() {
+					continue;
+				}
+
+				if remaining.is_empty() {
+					unresolved.push(neighbour);
+					*domains.get_checked_mut(neighbour) = None;
+				} else {
+					*domains.get_checked_mut(neighbour) = Some(remaining);
+					queue.push_back(neighbour);
+				}
+			}
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+	use grid_2d::Size;
+	use super::*;
+	use crate::Rotation;
+	use crate::validate::validate;
+
+	fn tileset() -> Tileset {
+		Tileset::load(&Path::new(crate::tileset::ASSET_DIRECTORY).join("alien.ron"))
+	}
+
+	// Ground everywhere, with water painted over the cells marked in `water`.
+	fn autotile(tileset: &Tileset, water: &[&str]) -> Autotiled {
+		let size = Size::new(water[0].len() as u32, water.len() as u32);
+		let mask = Grid::new_fn(size, |position| water[position.y as usize].as_bytes()[position.x as usize] == b'~');
+		let terrain = Grid::new_fn(size, |position| match mask.get_checked(position) {
+			true => TileLabel::new("water"),
+			false => TileLabel::new("ground"),
+		});
+
+		Autotiler::water(tileset).apply(&terrain, &mask)
+	}
+
+	fn label(autotiled: &Autotiled, x: i32, y: i32) -> &TileLabel {
+		autotiled.labels.get_checked(Coord::new(x, y))
+	}
+
+	const LAKE: &[&str] = &[".....", ".~~~.", ".~~~.", ".~~~.", "....."];
+
+	#[test]
+	fn straight_shoreline_gets_edges() {
+		let tileset = tileset();
+		let autotiled = autotile(&tileset, LAKE);
+
+		assert!(autotiled.unresolved.is_empty());
+		assert!(validate(&autotiled.labels, &tileset).is_empty());
+		assert!(tileset.same_tile(label(&autotiled, 2, 1), &TileLabel::new("water_edge")));
+		assert!(tileset.same_tile(label(&autotiled, 2, 2), &TileLabel::new("water")));
+	}
+
+	#[test]
+	fn corners_are_turned_to_fit() {
+		let tileset = tileset();
+		let autotiled = autotile(&tileset, LAKE);
+		let corner = |rotation| TileLabel { rotation, ..TileLabel::new("water_corner") };
+
+		assert!(tileset.same_tile(label(&autotiled, 1, 1), &corner(Rotation::Normal)));
+		assert!(tileset.same_tile(label(&autotiled, 3, 1), &corner(Rotation::Plus90)));
+		assert!(tileset.same_tile(label(&autotiled, 1, 3), &corner(Rotation::Minus90)));
+		assert!(tileset.same_tile(label(&autotiled, 3, 3), &corner(Rotation::Opposite)));
+	}
+
+	#[test]
+	fn water_with_nothing_that_fits_is_unresolved() {
+		let tileset = tileset();
+		let autotiled = autotile(&tileset, &["...", ".~.", "..."]);
+
+		assert_eq!(autotiled.unresolved, vec![Coord::new(1, 1)]);
+		assert_eq!(label(&autotiled, 1, 1).label, "water");
+	}
+}
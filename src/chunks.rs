@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use legion::*;
+use grid_2d::{Coord, Grid, Size};
+use crate::{Tile, TileLabel, Visibility, Player, Camera, Egg, Rotation, load_tile};
+use crate::map::{MapFile, ObjectSpawn, Overrides};
+use crate::prefabs::{Prefabs, Prefab};
+use crate::tileset::Tileset;
+use crate::light::Light;
+use crate::level::{Levels, LevelId};
+use crate::tween::{Tween, PlayerTween};
+use crate::energy::{Noise, Energy};
+use crate::items::Fuel;
+use crate::weather::hash_with;
+
+pub const CHUNK_SIZE: i32 = 32;
+// The overworld is kept in memory as a window of this many chunks across, centred on the player.
+const WINDOW_CHUNKS: i32 = 5;
+// Chunks that leave the window are saved here, until they're needed again.
+pub const SAVE_DIRECTORY: &str = "saves/chunks";
+
+// Tiles and entities in the window use coordinates relative to its top left corner, which moves a
+// chunk at a time as the player leaves the centre chunk.
+pub struct Overworld {
+	// The chunk at the top left of the window.
+	pub origin: Coord,
+	// The hand-made part of the overworld, starting at (0, 0). Everywhere else is generated.
+	authored: MapFile,
+	// The world's seed, which everywhere that's generated varies with.
+	seed: u64,
+	// Where chunks are saved, which is only somewhere other than `SAVE_DIRECTORY` for replays.
+	saves: PathBuf,
+}
+
+// How part of a level is saved. Caves are saved like this too, as one big chunk.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct ChunkFile {
+	tiles: Grid<TileLabel>,
+	explored: Grid<bool>,
+	// Entities are saved as the prefab they were spawned from, along with anything that's changed
+	// since. Positions are within the chunk.
+	#[serde(default)]
+	entities: Vec<ObjectSpawn>,
+}
+
+struct Chunk {
+	tiles: Grid<Tile>,
+	vis_map: Grid<Visibility>,
+}
+
+impl ChunkFile {
+	pub fn read(path: &Path) -> Self {
+		let bytes = std::fs::read(path)
+			.unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+		ron::de::from_bytes(&bytes)
+			.unwrap_or_else(|err| panic!("Failed to parse {}: {}", path.display(), err))
+	}
+
+	pub fn write(&self, path: &Path) {
+		let contents = ron::ser::to_string(self).unwrap();
+		path.parent().map_or(Ok(()), std::fs::create_dir_all)
+			.and_then(|()| std::fs::write(path, contents))
+			.unwrap_or_else(|err| panic!("Failed to write {}: {}", path.display(), err));
+	}
+
+	// Takes the part of the level with its top left corner at `offset`.
+	pub fn save(world: &World, map: &Grid<Tile>, vis_map: &Grid<Visibility>, offset: Coord, size: Size) -> Self {
+		let inside = |position: Coord| (position - offset).is_valid(size);
+
+		Self {
+			tiles: Grid::new_fn(size, |local| {
+				let tile = map.get_checked(offset + local);
+				TileLabel {
+					label: tile.tag.label().to_string(),
+					rotation: tile.rotation,
+					flip: tile.flip,
+					subsection: (0, 0),
+				}
+			}),
+			explored: Grid::new_fn(size, |local| *vis_map.get_checked(offset + local) != Visibility::Invisible),
+			entities: <(&Coord, &Prefab, Option<&Egg>, Option<&Rotation>, Option<&Fuel>, Option<&Energy>)>::query().iter(world)
+				.filter(|(position, _, _, _, _, _)| inside(**position))
+				.map(|(position, prefab, egg, rotation, fuel, energy)| ObjectSpawn {
+					position: *position - offset,
+					prefab: prefab.0.clone(),
+					overrides: Overrides {
+						hatch_timer: egg.map(|egg| egg.0),
+						rotation: rotation.copied(),
+						fuel: fuel.map(|fuel| fuel.0),
+						energy: energy.map(|energy| energy.0),
+					},
+				})
+				.collect(),
+		}
+	}
+
+	// Spawns the entities with the top left corner at `offset`, returning the tiles and what's been seen of them.
+	pub fn load(self, world: &mut World, tileset: &Tileset, prefabs: &Prefabs, offset: Coord) -> (Grid<Tile>, Grid<Visibility>) {
+		for object in &self.entities {
+			prefabs.spawn_object(world, offset, object);
+		}
+
+		let tiles = Grid::new_grid_map_with_coord(self.tiles, |local, label| {
+			load_tile(world, offset + local, &label, tileset, prefabs)
+		});
+		let vis_map = self.explored.map(|explored| if explored {
+			Visibility::PreviouslyVisible
+		} else {
+			Visibility::Invisible
+		});
+
+		(tiles, vis_map)
+	}
+}
+
+fn chunk_size() -> Size {
+	Size::new(CHUNK_SIZE as u32, CHUNK_SIZE as u32)
+}
+
+fn window_size() -> Size {
+	chunk_size() * WINDOW_CHUNKS as u32
+}
+
+fn window_centre() -> Coord {
+	Coord::new(WINDOW_CHUNKS / 2, WINDOW_CHUNKS / 2)
+}
+
+fn in_window(index: Coord) -> bool {
+	index.x >= 0 && index.y >= 0 && index.x < WINDOW_CHUNKS && index.y < WINDOW_CHUNKS
+}
+
+// The index of the chunk containing a position within the window.
+fn chunk_index(position: Coord) -> Coord {
+	Coord::new(position.x.div_euclid(CHUNK_SIZE), position.y.div_euclid(CHUNK_SIZE))
+}
+
+// Forgets the chunks saved by a previous game.
+pub fn clear_saves(directory: &Path) {
+	if directory.exists() {
+		std::fs::remove_dir_all(directory)
+			.unwrap_or_else(|err| panic!("Failed to remove {}: {}", directory.display(), err));
+	}
+}
+
+impl Overworld {
+	// Centres the window on the player's starting position.
+	pub fn new(authored: MapFile, start: Coord, seed: u64, saves: &Path) -> Self {
+		Self {
+			origin: chunk_index(start) - window_centre(),
+			authored,
+			seed,
+			saves: saves.to_path_buf(),
+		}
+	}
+
+	fn chunk_path(&self, chunk: Coord) -> PathBuf {
+		self.saves.join(format!("{}_{}.ron", chunk.x, chunk.y))
+	}
+
+	pub fn to_absolute(&self, position: Coord) -> Coord {
+		position + self.origin * CHUNK_SIZE
+	}
+
+	pub fn to_local(&self, position: Coord) -> Coord {
+		position - self.origin * CHUNK_SIZE
+	}
+
+	pub fn authored(&self) -> &MapFile {
+		&self.authored
+	}
+
+	// Changes made here only show up in chunks as they're generated, so the editor patches the
+	// window as well.
+	pub fn authored_mut(&mut self) -> &mut MapFile {
+		&mut self.authored
+	}
+
+	// The editor changes the authored map outside the window as well. A chunk saved before then
+	// would hide the change, so it's thrown away to be made again from the map.
+	pub fn forget_saved_chunk(&self, position: Coord) {
+		let path = self.chunk_path(chunk_index(position));
+
+		if path.exists() {
+			std::fs::remove_file(&path)
+				.unwrap_or_else(|err| panic!("Failed to remove {}: {}", path.display(), err));
+		}
+	}
+
+	// Used when the map is hot-reloaded. Saved chunks may be out of date, so they're thrown away.
+	pub fn set_authored(&mut self, authored: MapFile) {
+		self.authored = authored;
+		clear_saves(&self.saves);
+	}
+
+	// Loads every chunk in the window, spawning their entities.
+	pub fn build_window(&self, world: &mut World, tileset: &Tileset, prefabs: &Prefabs) -> (Grid<Tile>, Grid<Visibility>) {
+		let chunks = (0 .. WINDOW_CHUNKS)
+			.flat_map(|x| (0 .. WINDOW_CHUNKS).map(move |y| Coord::new(x, y)))
+			.map(|index| (index, self.load_chunk(world, tileset, prefabs, index)))
+			.collect();
+
+		assemble(chunks, |_| None)
+	}
+
+	fn load_chunk(&self, world: &mut World, tileset: &Tileset, prefabs: &Prefabs, index: Coord) -> Chunk {
+		let chunk = self.origin + index;
+		let offset = index * CHUNK_SIZE;
+		let path = self.chunk_path(chunk);
+
+		let file = if path.exists() {
+			ChunkFile::read(&path)
+		} else {
+			let tiles = Grid::new_fn(chunk_size(), |local| self.label_at(chunk * CHUNK_SIZE + local));
+
+			let authored_objects = self.authored.objects.iter()
+				.filter(|object| chunk_index(object.position) == chunk)
+				.map(|object| ObjectSpawn {
+					position: object.position - chunk * CHUNK_SIZE,
+					..object.clone()
+				});
+
+			ChunkFile {
+				entities: authored_objects.chain(generated_entities(chunk, &tiles, prefabs, self.seed)).collect(),
+				tiles,
+				explored: Grid::new_copy(chunk_size(), false),
+			}
+		};
+
+		let (tiles, vis_map) = file.load(world, tileset, prefabs, offset);
+		Chunk { tiles, vis_map }
+	}
+
+	// Writes out every chunk in the window, leaving them loaded.
+	pub fn save_window(&self, world: &World, map: &Grid<Tile>, vis_map: &Grid<Visibility>) {
+		for x in 0 .. WINDOW_CHUNKS {
+			for y in 0 .. WINDOW_CHUNKS {
+				self.save_chunk(world, Coord::new(x, y), map, vis_map);
+			}
+		}
+	}
+
+	// Writes out a chunk that's leaving the window, and removes its entities.
+	fn unload_chunk(&self, world: &mut World, index: Coord, map: &Grid<Tile>, vis_map: &Grid<Visibility>) {
+		self.save_chunk(world, index, map, vis_map);
+
+		let entities: Vec<Entity> = <(Entity, &Coord)>::query().iter(world)
+			.filter(|(_, position)| chunk_index(**position) == index)
+			.map(|(entity, _)| *entity)
+			.collect();
+
+		for entity in entities {
+			world.remove(entity);
+		}
+	}
+
+	fn save_chunk(&self, world: &World, index: Coord, map: &Grid<Tile>, vis_map: &Grid<Visibility>) {
+		ChunkFile::save(world, map, vis_map, index * CHUNK_SIZE, chunk_size())
+			.write(&self.chunk_path(self.origin + index));
+	}
+
+	// Visited caves are saved alongside the chunks, and thrown away with them.
+	pub fn cave_path(&self, entrance: Coord) -> PathBuf {
+		self.saves.join(format!("cave_{}_{}.ron", entrance.x, entrance.y))
+	}
+
+	fn label_at(&self, position: Coord) -> TileLabel {
+		match self.authored.terrain.get(position) {
+			Some(label) => label.clone(),
+			None => TileLabel {
+				label: generated_label(position, self.seed).to_string(),
+				rotation: Default::default(),
+				flip: Default::default(),
+				subsection: (0, 0),
+			},
+		}
+	}
+}
+
+// Stitches loaded chunks together into the window, filling in the rest from `existing`.
+fn assemble(
+	mut chunks: HashMap<Coord, Chunk>,
+	existing: impl Fn(Coord) -> Option<(Tile, Visibility)>,
+) -> (Grid<Tile>, Grid<Visibility>) {
+	let cells = Grid::new_fn(window_size(), |position| {
+		match chunks.get_mut(&chunk_index(position)) {
+			Some(chunk) => {
+				let local = position - chunk_index(position) * CHUNK_SIZE;
+				(chunk.tiles.get_checked(local).clone(), *chunk.vis_map.get_checked(local))
+			},
+			None => existing(position).expect("Every chunk in the window should be loaded"),
+		}
+	});
+
+	let map = cells.map_ref(|(tile, _)| tile.clone());
+	let vis_map = cells.map(|(_, vis)| vis);
+	(map, vis_map)
+}
+
+// Called after the player moves. Once they leave the centre chunk, the window is moved so that
+// it's centred on them again.
+pub fn recentre(world: &mut World, resources: &mut Resources) {
+	if resources.get::<Levels>().unwrap().current != LevelId::Surface {
+		return;
+	}
+
+	let shift = chunk_index(resources.get::<Player>().unwrap().position) - window_centre();
+
+	if shift == Coord::new(0, 0) {
+		return;
+	}
+
+	let old_map = resources.remove::<Grid<Tile>>().unwrap();
+	let old_vis_map = resources.remove::<Grid<Visibility>>().unwrap();
+	let mut overworld = resources.get_mut::<Overworld>().unwrap();
+	let tileset = resources.get::<Tileset>().unwrap();
+	let prefabs = resources.get::<Prefabs>().unwrap();
+
+	for x in 0 .. WINDOW_CHUNKS {
+		for y in 0 .. WINDOW_CHUNKS {
+			let index = Coord::new(x, y);
+
+			if !in_window(index - shift) {
+				overworld.unload_chunk(world, index, &old_map, &old_vis_map);
+			}
+		}
+	}
+
+	// Everything that's left moves with the window.
+	let offset = shift * -CHUNK_SIZE;
+
+	for position in <&mut Coord>::query().iter_mut(world) {
+		*position += offset;
+	}
+
+	for tween in <&mut Tween>::query().iter_mut(world) {
+		tween.translate(offset);
+	}
+
+	overworld.origin += shift;
+
+	let new_chunks = (0 .. WINDOW_CHUNKS)
+		.flat_map(|x| (0 .. WINDOW_CHUNKS).map(move |y| Coord::new(x, y)))
+		.filter(|index| !in_window(*index + shift))
+		.map(|index| (index, overworld.load_chunk(world, &tileset, &prefabs, index)))
+		.collect();
+
+	let (map, vis_map) = assemble(new_chunks, |position| {
+		let old = position - offset;
+		Some((old_map.get(old)?.clone(), *old_vis_map.get(old)?))
+	});
+
+	drop(overworld);
+	drop(tileset);
+	drop(prefabs);
+
+	resources.insert(Grid::new_copy(map.size(), Light::default()));
+	resources.insert(map);
+	resources.insert(vis_map);
+
+	resources.get_mut::<Player>().unwrap().position += offset;
+	resources.get_mut::<PlayerTween>().unwrap().0.translate(offset);
+	resources.get_mut::<Noise>().unwrap().origin += offset;
+	resources.get_mut::<Camera>().unwrap().position += cgmath::vec2(offset.x as f32, offset.y as f32);
+}
+
+// Smoothly varying noise between 0 and 1, with features roughly `scale` tiles across.
+fn value_noise(position: Coord, scale: i32, seed: u64) -> f32 {
+	let lattice = |x: i32, y: i32| {
+		(hash_with(seed, ((x as u64) << 32) | y as u32 as u64) % 1024) as f32 / 1023.0
+	};
+
+	let cell = Coord::new(position.x.div_euclid(scale), position.y.div_euclid(scale));
+	let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+	let tx = smooth(position.x.rem_euclid(scale) as f32 / scale as f32);
+	let ty = smooth(position.y.rem_euclid(scale) as f32 / scale as f32);
+
+	let top = lattice(cell.x, cell.y) * (1.0 - tx) + lattice(cell.x + 1, cell.y) * tx;
+	let bottom = lattice(cell.x, cell.y + 1) * (1.0 - tx) + lattice(cell.x + 1, cell.y + 1) * tx;
+	top * (1.0 - ty) + bottom * ty
+}
+
+// Rolls the overworld's spawn table across open ground the first time a chunk is loaded.
+fn generated_entities(chunk: Coord, tiles: &Grid<TileLabel>, prefabs: &Prefabs, seed: u64) -> Vec<ObjectSpawn> {
+	tiles.enumerate()
+		.filter(|(_, tile)| matches!(&tile.label[..], "ground" | "snowy_ground" | "cave"))
+		.filter_map(|(local, _)| {
+			let position = chunk * CHUNK_SIZE + local;
+			let roll = (hash_with(hash_with(seed, 4), ((position.x as u64) << 32) | position.y as u32 as u64) % 1_000_000) as f32 / 1_000_000.0;
+
+			// Each entry takes up its own slice of the roll, so at most one thing spawns per tile.
+			let mut total = 0.0;
+			prefabs.spawn_table("overworld").iter()
+				.find(|spawn| {
+					total += spawn.chance;
+					roll < total
+				})
+				.map(|spawn| ObjectSpawn {
+					position: local,
+					prefab: spawn.prefab.clone(),
+					overrides: Overrides::default(),
+				})
+		})
+		.collect()
+}
+
+// Each layer of noise is drawn under its own salt, so the layers don't line up with each other.
+fn generated_label(position: Coord, seed: u64) -> &'static str {
+	let height = value_noise(position, 16, seed) * 0.7 + value_noise(position, 4, hash_with(seed, 1)) * 0.3;
+	let snow = value_noise(position, 24, hash_with(seed, 2));
+	let detail = hash_with(hash_with(seed, 3), ((position.x as u64) << 32) | position.y as u32 as u64) % 400;
+
+	if height > 0.7 {
+		"rocks"
+	} else if detail == 0 {
+		"cave_enterance"
+	} else if snow > 0.55 {
+		"snowy_ground"
+	} else {
+		"ground"
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::load_world;
+	use crate::tileset::ASSET_DIRECTORY;
+
+	#[test]
+	fn fuel_and_energy_survive_saving() {
+		let assets = Path::new(ASSET_DIRECTORY);
+		let tileset = Tileset::load(&assets.join("alien.ron"));
+		let prefabs = Prefabs::load(&assets.join("prefabs.ron"));
+		let size = Size::new(4, 4);
+
+		let mut world = World::default();
+		let map = load_world(&mut world, Grid::new_fn(size, |_| TileLabel::new("ground")), &tileset, &prefabs);
+		let vis_map = Grid::new_copy(size, Visibility::Invisible);
+
+		let flare = prefabs.spawn(&mut world, Coord::new(1, 1), "lit_flare");
+		let egg = prefabs.spawn(&mut world, Coord::new(2, 2), "egg");
+		world.entry(flare).unwrap().get_component_mut::<Fuel>().unwrap().0 = 42;
+		world.entry(egg).unwrap().get_component_mut::<Energy>().unwrap().0 = 7;
+
+		let path = std::env::temp_dir().join("snowy_chunk_test.ron");
+		ChunkFile::save(&world, &map, &vis_map, Coord::new(0, 0), size).write(&path);
+		let saved = ChunkFile::read(&path);
+		let _ = std::fs::remove_file(&path);
+
+		let mut loaded = World::default();
+		saved.load(&mut loaded, &tileset, &prefabs, Coord::new(0, 0));
+
+		let fuel: Vec<_> = <(&Coord, &Fuel)>::query().iter(&loaded).map(|(position, fuel)| (*position, fuel.0)).collect();
+		let energy: Vec<_> = <(&Coord, &Energy)>::query().iter(&loaded).map(|(position, energy)| (*position, energy.0)).collect();
+		assert_eq!(fuel, vec![(Coord::new(1, 1), 42)]);
+		assert_eq!(energy, vec![(Coord::new(2, 2), 7)]);
+	}
+}
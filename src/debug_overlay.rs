@@ -0,0 +1,127 @@
+use std::collections::{HashSet, VecDeque};
+use legion::*;
+use legion::world::SubWorld;
+use grid_2d::{Coord, Grid};
+use crate::{Tile, Player, BlocksMovement, in_line_of_sight};
+use crate::tileset::Direction;
+use crate::light::Light;
+use crate::weather::Sky;
+
+const BLOCKS_BOTH: [f32; 4] = [0.6, 0.2, 0.8, 0.6];
+const BLOCKS_MOVEMENT: [f32; 4] = [0.9, 0.2, 0.2, 0.6];
+const BLOCKS_SIGHT: [f32; 4] = [0.2, 0.4, 0.9, 0.6];
+const OCCUPIED: [f32; 4] = [1.0, 0.6, 0.1, 0.6];
+const IN_SIGHT: [f32; 4] = [0.2, 0.9, 0.3, 0.25];
+const UNLIT: [f32; 4] = [0.9, 0.8, 0.2, 0.4];
+const OUT_OF_SIGHT: [f32; 4] = [0.0, 0.0, 0.0, 0.5];
+const OUT_OF_RANGE: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+const UNREACHABLE: [f32; 4] = [0.0, 0.0, 0.0, 0.75];
+// Distances fade from the near colour to the far one over this many steps.
+const FAR: u32 = 40;
+
+// Tints each tile to show why it can or can't be walked on or seen.
+#[derive(Copy, Clone, PartialEq)]
+pub enum OverlayMode {
+	Off,
+	// What blocks movement and sight, what's taken up by an entity, and which tiles within the
+	// sight radius have a clear line to the player and are lit well enough to be seen.
+	Blocking,
+	// How many steps away each tile is for something walking from the player.
+	Distances,
+}
+
+pub struct DebugOverlay {
+	pub mode: OverlayMode,
+}
+
+impl Default for DebugOverlay {
+	fn default() -> Self {
+		Self { mode: OverlayMode::Off }
+	}
+}
+
+impl DebugOverlay {
+	pub fn cycle(&mut self) {
+		self.mode = match self.mode {
+			OverlayMode::Off => OverlayMode::Blocking,
+			OverlayMode::Blocking => OverlayMode::Distances,
+			OverlayMode::Distances => OverlayMode::Off,
+		};
+	}
+
+	// The overlay colour for every tile, or `None` if the overlay is off.
+	pub fn colours(&self, world: &SubWorld, map: &Grid<Tile>, light_map: &Grid<Light>, player: &Player, sky: &Sky) -> Option<Grid<[f32; 4]>> {
+		let occupied: HashSet<Coord> = <(&Coord, &BlocksMovement)>::query().iter(world)
+			.map(|(position, _)| *position)
+			.collect();
+
+		let blocking = |position: Coord, tile: &Tile| match (tile.tag.blocks_movement(), tile.tag.blocks_sight()) {
+			(true, true) => Some(BLOCKS_BOTH),
+			(true, false) => Some(BLOCKS_MOVEMENT),
+			(false, true) => Some(BLOCKS_SIGHT),
+			(false, false) if occupied.contains(&position) => Some(OCCUPIED),
+			_ => None,
+		};
+
+		match self.mode {
+			OverlayMode::Off => None,
+			OverlayMode::Blocking => Some(Grid::new_grid_map_ref_with_coord(map, |position, tile| {
+				blocking(position, tile).unwrap_or_else(|| {
+					let radius = sky.sight_radius as u32;
+
+					if position.distance2(player.position) > radius.pow(2) {
+						OUT_OF_RANGE
+					} else if !in_line_of_sight(map, position, player.position) {
+						OUT_OF_SIGHT
+					} else if light_map.get_checked(position).can_see(tile) {
+						IN_SIGHT
+					} else {
+						UNLIT
+					}
+				})
+			})),
+			OverlayMode::Distances => {
+				let distances = distances(map, &occupied, player.position);
+
+				Some(Grid::new_grid_map_ref_with_coord(map, |position, tile| {
+					blocking(position, tile).unwrap_or_else(|| match distances.get_checked(position) {
+						Some(distance) => {
+							let far = (*distance).min(FAR) as f32 / FAR as f32;
+							[far, 1.0 - far, 1.0 - far * 0.5, 0.5]
+						},
+						None => UNREACHABLE,
+					})
+				}))
+			},
+		}
+	}
+}
+
+// Breadth first from `from`, around blocked tiles and anything in the way.
+fn distances(map: &Grid<Tile>, occupied: &HashSet<Coord>, from: Coord) -> Grid<Option<u32>> {
+	let mut distances = Grid::new_copy(map.size(), None);
+	let mut queue = VecDeque::new();
+
+	if let Some(distance) = distances.get_mut(from) {
+		*distance = Some(0);
+		queue.push_back(from);
+	}
+
+	while let Some(position) = queue.pop_front() {
+		let next = distances.get_checked(position).unwrap_or(0) + 1;
+
+		for &direction in &Direction::ALL {
+			let neighbour = position + direction.offset();
+
+			let open = map.get(neighbour).is_some_and(|tile| !tile.tag.blocks_movement())
+				&& !occupied.contains(&neighbour);
+
+			if open && distances.get_checked(neighbour).is_none() {
+				*distances.get_checked_mut(neighbour) = Some(next);
+				queue.push_back(neighbour);
+			}
+		}
+	}
+
+	distances
+}
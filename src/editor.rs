@@ -0,0 +1,656 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use legion::*;
+use grid_2d::{Coord, Grid, Size};
+use crate::{Tile, TileLabel, Rotation, Flip, Camera, load_tile};
+use crate::chunks::Overworld;
+use crate::map::{ObjectSpawn, Overrides};
+use crate::prefabs::{Prefabs, Prefab};
+use crate::tileset::Tileset;
+use crate::renderer::BufferRenderer;
+use crate::autotile::Autotiler;
+
+const MAX_UNDO: usize = 100;
+const SELECTION: [f32; 4] = [0.3, 0.6, 1.0, 0.5];
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Tool {
+	Paint,
+	Fill,
+	Select,
+	Object,
+}
+
+// A single change to the authored map. Positions are relative to the map, not the window.
+enum Change {
+	Terrain { position: Coord, before: TileLabel, after: TileLabel },
+	AddObject(ObjectSpawn),
+	RemoveObject(ObjectSpawn),
+}
+
+impl Change {
+	fn inverse(&self) -> Self {
+		match self {
+			Self::Terrain { position, before, after } => Self::Terrain {
+				position: *position,
+				before: after.clone(),
+				after: before.clone(),
+			},
+			Self::AddObject(object) => Self::RemoveObject(object.clone()),
+			Self::RemoveObject(object) => Self::AddObject(object.clone()),
+		}
+	}
+}
+
+// Edits the authored overworld map, patching the loaded window so that changes show up straight away.
+pub struct Editor {
+	pub enabled: bool,
+	tool: Tool,
+	rotation: Rotation,
+	flip: Flip,
+	labels: Vec<String>,
+	label_index: usize,
+	prefabs: Vec<String>,
+	prefab_index: usize,
+	// The tile under the mouse, in window coordinates.
+	cursor: Option<Coord>,
+	// Where the mouse was pressed, for selecting.
+	drag_start: Option<Coord>,
+	// The corners of the selected area of the authored map.
+	selection: Option<(Coord, Coord)>,
+	// Terrain copied from a selection, to paste with its top left corner under the cursor.
+	clipboard: Option<Grid<TileLabel>>,
+	// Changes made while the mouse is held down, which are undone together.
+	stroke: Option<Vec<Change>>,
+	undo: VecDeque<Vec<Change>>,
+	redo: Vec<Vec<Change>>,
+	map_path: PathBuf,
+	// Tiles that the last edit left without a water tile that fits.
+	unresolved: usize,
+}
+
+fn palette(tileset: &Tileset) -> Vec<String> {
+	let mut labels: Vec<String> = tileset.tiles.keys().cloned().collect();
+	labels.sort();
+	labels
+}
+
+impl Editor {
+	pub fn new(map_path: PathBuf, tileset: &Tileset, prefabs: &Prefabs) -> Self {
+		Self {
+			enabled: false,
+			tool: Tool::Paint,
+			rotation: Rotation::Normal,
+			flip: Flip::None,
+			labels: palette(tileset),
+			label_index: 0,
+			prefabs: prefabs.names(),
+			prefab_index: 0,
+			cursor: None,
+			drag_start: None,
+			selection: None,
+			clipboard: None,
+			stroke: None,
+			undo: VecDeque::new(),
+			redo: Vec::new(),
+			map_path,
+			unresolved: 0,
+		}
+	}
+
+	// Keeps the palette in step with a reloaded tileset, staying on the same label if it's still there.
+	pub fn reload(&mut self, tileset: &Tileset) {
+		let selected = self.labels[self.label_index].clone();
+		self.labels = palette(tileset);
+		self.label_index = self.labels.iter().position(|label| *label == selected).unwrap_or(0);
+	}
+
+	// Shown in the window title, as there's no text rendering.
+	pub fn status(&self) -> String {
+		let status = match self.tool {
+			Tool::Object => format!("Editor - Object: {}", self.prefabs.get(self.prefab_index).map(|name| &name[..]).unwrap_or("none")),
+			tool => format!("Editor - {:?}: {} ({:?}, {:?} flip)", tool, self.labels[self.label_index], self.rotation, self.flip),
+		};
+
+		match self.unresolved {
+			0 => status,
+			count => format!("{} - no water tile fits {} tiles", status, count),
+		}
+	}
+
+	fn label(&self) -> TileLabel {
+		TileLabel {
+			label: self.labels[self.label_index].clone(),
+			rotation: self.rotation,
+			flip: self.flip,
+			subsection: (0, 0),
+		}
+	}
+
+	fn commit(&mut self, changes: Vec<Change>) {
+		if changes.is_empty() {
+			return;
+		}
+
+		if self.undo.len() == MAX_UNDO {
+			self.undo.pop_front();
+		}
+
+		self.undo.push_back(changes);
+		self.redo.clear();
+	}
+
+	// Picks the tiles around what was painted while the mouse was held down, and keeps it as one
+	// step to undo.
+	fn finish_stroke(&mut self, target: &mut Target) {
+		if let Some(mut stroke) = self.stroke.take() {
+			self.unresolved = target.autotile(&mut stroke);
+			self.commit(stroke);
+		}
+	}
+}
+
+// Everything an edit touches.
+struct Target<'a> {
+	world: &'a mut World,
+	overworld: &'a mut Overworld,
+	map: &'a mut Grid<Tile>,
+	tileset: &'a Tileset,
+	prefabs: &'a Prefabs,
+}
+
+impl Target<'_> {
+	fn label_at(&self, position: Coord) -> Option<&TileLabel> {
+		self.overworld.authored().terrain.get(position)
+	}
+
+	// The authored position under a tile in the window, if it's part of the authored map.
+	fn authored_position(&self, cursor: Coord) -> Option<Coord> {
+		let position = self.overworld.to_absolute(cursor);
+		self.label_at(position).map(|_| position)
+	}
+
+	// The part of the rectangle between two tiles in the window that's in the authored map.
+	fn authored_rectangle(&self, a: Coord, b: Coord) -> Option<(Coord, Coord)> {
+		let (a, b) = (self.overworld.to_absolute(a), self.overworld.to_absolute(b));
+		let terrain = &self.overworld.authored().terrain;
+
+		let min = Coord::new(a.x.min(b.x).max(0), a.y.min(b.y).max(0));
+		let max = Coord::new(
+			a.x.max(b.x).min(terrain.width() as i32 - 1),
+			a.y.max(b.y).min(terrain.height() as i32 - 1),
+		);
+
+		(min.x <= max.x && min.y <= max.y).then_some((min, max))
+	}
+
+	fn apply(&mut self, change: &Change) {
+		match change {
+			Change::Terrain { position, before, after } => {
+				*self.overworld.authored_mut().terrain.get_checked_mut(*position) = after.clone();
+
+				let local = self.overworld.to_local(*position);
+
+				if self.map.get(local).is_some() {
+					// Labels such as "egg" place an entity as well as terrain.
+					if let Some(prefab) = self.tileset.tiles.get(&before.label).and_then(|tile| tile.spawn.as_ref()) {
+						remove_entity(self.world, local, prefab);
+					}
+
+					*self.map.get_checked_mut(local) = load_tile(self.world, local, after, self.tileset, self.prefabs);
+				} else {
+					self.overworld.forget_saved_chunk(*position);
+				}
+			},
+			Change::AddObject(object) => {
+				self.overworld.authored_mut().objects.push(object.clone());
+
+				if self.map.get(self.overworld.to_local(object.position)).is_some() {
+					let offset = self.overworld.to_local(Coord::new(0, 0));
+					self.prefabs.spawn_object(self.world, offset, object);
+				} else {
+					self.overworld.forget_saved_chunk(object.position);
+				}
+			},
+			Change::RemoveObject(object) => {
+				let objects = &mut self.overworld.authored_mut().objects;
+
+				if let Some(index) = objects.iter().position(|other| other.position == object.position && other.prefab == object.prefab) {
+					objects.remove(index);
+				}
+
+				let local = self.overworld.to_local(object.position);
+
+				if self.map.get(local).is_some() {
+					remove_entity(self.world, local, &object.prefab);
+				} else {
+					self.overworld.forget_saved_chunk(object.position);
+				}
+			},
+		}
+	}
+
+	fn set_terrain(&mut self, position: Coord, label: &TileLabel, changes: &mut Vec<Change>) {
+		let before = match self.label_at(position) {
+			Some(before) if before != label => before.clone(),
+			_ => return,
+		};
+
+		let change = Change::Terrain { position, before, after: label.clone() };
+		self.apply(&change);
+		changes.push(change);
+	}
+
+	// Once water has been painted or painted over, picks the edges and corners around it. Returns
+	// how many tiles no water tile fits.
+	fn autotile(&mut self, changes: &mut Vec<Change>) -> usize {
+		let autotiler = Autotiler::water(self.tileset);
+
+		let water: Vec<Coord> = changes.iter()
+			.filter_map(|change| match change {
+				Change::Terrain { position, before, after } if autotiler.is_member(before) || autotiler.is_member(after) => Some(*position),
+				_ => None,
+			})
+			.collect();
+
+		if water.is_empty() {
+			return 0;
+		}
+
+		// The water that changed and the tiles next to it can be picked again. The ring around
+		// that is only looked at, so that what's picked fits in with it.
+		let terrain = &self.overworld.authored().terrain;
+		let bounds = Coord::new(terrain.width() as i32 - 1, terrain.height() as i32 - 1);
+		let min = water.iter().fold(bounds, |min, position| Coord::new(min.x.min(position.x), min.y.min(position.y)));
+		let max = water.iter().fold(Coord::new(0, 0), |max, position| Coord::new(max.x.max(position.x), max.y.max(position.y)));
+
+		let offset = Coord::new((min.x - 2).max(0), (min.y - 2).max(0));
+		let end = Coord::new((max.x + 2).min(bounds.x), (max.y + 2).min(bounds.y));
+		let size = Size::new((end.x - offset.x + 1) as u32, (end.y - offset.y + 1) as u32);
+
+		let area = Grid::new_fn(size, |local| terrain.get_checked(offset + local).clone());
+		let mask = Grid::new_fn(size, |local| {
+			let position = offset + local;
+			let near = position.x >= min.x - 1 && position.y >= min.y - 1 && position.x <= max.x + 1 && position.y <= max.y + 1;
+			near && autotiler.is_member(area.get_checked(local))
+		});
+		let autotiled = autotiler.apply(&area, &mask);
+
+		for (local, label) in autotiled.labels.enumerate() {
+			self.set_terrain(offset + local, label, changes);
+		}
+
+		autotiled.unresolved.len()
+	}
+
+	// Replaces the connected area of matching tiles.
+	fn flood_fill(&mut self, start: Coord, label: &TileLabel, changes: &mut Vec<Change>) {
+		let target = match self.label_at(start) {
+			Some(target) if target != label => target.clone(),
+			_ => return,
+		};
+
+		let mut stack = vec![start];
+
+		while let Some(position) = stack.pop() {
+			if self.label_at(position) != Some(&target) {
+				continue;
+			}
+
+			self.set_terrain(position, label, changes);
+
+			for offset in &[Coord::new(1, 0), Coord::new(-1, 0), Coord::new(0, 1), Coord::new(0, -1)] {
+				stack.push(position + *offset);
+			}
+		}
+	}
+}
+
+fn remove_entity(world: &mut World, position: Coord, prefab: &str) {
+	let entity = <(Entity, &Coord, &Prefab)>::query().iter(world)
+		.find(|(_, coord, other)| **coord == position && other.0 == prefab)
+		.map(|(entity, _, _)| *entity);
+
+	if let Some(entity) = entity {
+		world.remove(entity);
+	}
+}
+
+fn rectangle(a: Coord, b: Coord) -> impl Iterator<Item = Coord> {
+	let (min, max) = (Coord::new(a.x.min(b.x), a.y.min(b.y)), Coord::new(a.x.max(b.x), a.y.max(b.y)));
+	(min.y ..= max.y).flat_map(move |y| (min.x ..= max.x).map(move |x| Coord::new(x, y)))
+}
+
+// Runs `f` with the editor and everything that edits touch.
+fn with_target<R>(world: &mut World, resources: &mut Resources, f: impl FnOnce(&mut Editor, &mut Target) -> R) -> R {
+	let mut editor = resources.get_mut::<Editor>().unwrap();
+	let mut overworld = resources.get_mut::<Overworld>().unwrap();
+	let mut map = resources.get_mut::<Grid<Tile>>().unwrap();
+	let tileset = resources.get::<Tileset>().unwrap();
+	let prefabs = resources.get::<Prefabs>().unwrap();
+
+	let mut target = Target {
+		world,
+		overworld: &mut overworld,
+		map: &mut map,
+		tileset: &tileset,
+		prefabs: &prefabs,
+	};
+
+	f(&mut editor, &mut target)
+}
+
+// Returns true if the map was changed.
+pub fn cursor_moved(world: &mut World, resources: &mut Resources, pixel: (f64, f64)) -> bool {
+	let cursor = resources.get::<Camera>().unwrap().tile_at_pixel(pixel);
+
+	with_target(world, resources, |editor, target| {
+		editor.cursor = Some(cursor);
+
+		match (editor.tool, editor.stroke.take()) {
+			(Tool::Paint, Some(mut stroke)) => {
+				let changed = stroke.len();
+
+				if let Some(position) = target.authored_position(cursor) {
+					target.set_terrain(position, &editor.label(), &mut stroke);
+				}
+
+				let changed = stroke.len() != changed;
+				editor.stroke = Some(stroke);
+				changed
+			},
+			(_, stroke) => {
+				editor.stroke = stroke;
+				false
+			},
+		}
+	})
+}
+
+// Returns true if the map was changed.
+pub fn mouse_input(world: &mut World, resources: &mut Resources, left: bool, pressed: bool) -> bool {
+	with_target(world, resources, |editor, target| {
+		let cursor = match editor.cursor {
+			Some(cursor) => cursor,
+			None => return false,
+		};
+		let position = target.authored_position(cursor);
+		let mut changes = Vec::new();
+
+		match (editor.tool, left, pressed) {
+			(Tool::Paint, true, true) => {
+				if let Some(position) = position {
+					target.set_terrain(position, &editor.label(), &mut changes);
+				}
+				editor.stroke = Some(changes);
+				return true;
+			},
+			(Tool::Paint, true, false) => editor.finish_stroke(target),
+			(Tool::Fill, true, true) => {
+				if let Some(position) = position {
+					target.flood_fill(position, &editor.label(), &mut changes);
+				}
+				editor.unresolved = target.autotile(&mut changes);
+				editor.commit(changes);
+			},
+			(Tool::Select, true, true) => {
+				editor.drag_start = Some(cursor);
+				editor.selection = None;
+				return false;
+			},
+			(Tool::Select, true, false) => {
+				if let Some(start) = editor.drag_start.take() {
+					editor.selection = target.authored_rectangle(start, cursor);
+				}
+				return false;
+			},
+			(Tool::Object, true, true) => {
+				if let (Some(position), Some(prefab)) = (position, editor.prefabs.get(editor.prefab_index)) {
+					let change = Change::AddObject(ObjectSpawn {
+						position,
+						prefab: prefab.clone(),
+						overrides: Overrides::default(),
+					});
+					target.apply(&change);
+					changes.push(change);
+				}
+				editor.commit(changes);
+			},
+			(Tool::Object, false, true) => {
+				let removed: Vec<ObjectSpawn> = target.overworld.authored().objects.iter()
+					.filter(|object| Some(object.position) == position)
+					.cloned()
+					.collect();
+
+				for object in removed {
+					let change = Change::RemoveObject(object);
+					target.apply(&change);
+					changes.push(change);
+				}
+				editor.commit(changes);
+			},
+			// Right clicking picks up the tile under the cursor.
+			(_, false, true) => {
+				if let Some(label) = position.and_then(|position| target.label_at(position)) {
+					editor.label_index = editor.labels.iter().position(|other| *other == label.label).unwrap_or(editor.label_index);
+					editor.rotation = label.rotation;
+					editor.flip = label.flip;
+				}
+				return false;
+			},
+			_ => return false,
+		}
+
+		true
+	})
+}
+
+// Returns true if the map was changed.
+pub fn undo(world: &mut World, resources: &mut Resources, redo: bool) -> bool {
+	with_target(world, resources, |editor, target| {
+		let changes = if redo { editor.redo.pop() } else { editor.undo.pop_back() };
+
+		match changes {
+			Some(changes) => {
+				if redo {
+					changes.iter().for_each(|change| target.apply(change));
+					editor.undo.push_back(changes);
+				} else {
+					changes.iter().rev().for_each(|change| target.apply(&change.inverse()));
+					editor.redo.push(changes);
+				}
+				true
+			},
+			None => false,
+		}
+	})
+}
+
+pub fn save(resources: &Resources) {
+	let editor = resources.get::<Editor>().unwrap();
+	let overworld = resources.get::<Overworld>().unwrap();
+
+	let contents = ron::ser::to_string_pretty(overworld.authored(), Default::default()).unwrap();
+
+	match std::fs::write(&editor.map_path, contents) {
+		Ok(()) => println!("Saved {}", editor.map_path.display()),
+		Err(err) => eprintln!("Failed to write {}: {}", editor.map_path.display(), err),
+	}
+}
+
+// Finishes anything the old tool was in the middle of. Returns true if the map was changed.
+fn select_tool(world: &mut World, resources: &mut Resources, tool: Tool) -> bool {
+	with_target(world, resources, |editor, target| {
+		let changed = editor.stroke.is_some();
+		editor.finish_stroke(target);
+		editor.tool = tool;
+		editor.drag_start = None;
+		changed
+	})
+}
+
+// Keeps the terrain in the selection, to be pasted somewhere else.
+fn copy(resources: &Resources) {
+	let mut editor = resources.get_mut::<Editor>().unwrap();
+	let overworld = resources.get::<Overworld>().unwrap();
+
+	if let Some((min, max)) = editor.selection {
+		let size = Size::new((max.x - min.x + 1) as u32, (max.y - min.y + 1) as u32);
+		let terrain = &overworld.authored().terrain;
+		editor.clipboard = Some(Grid::new_fn(size, |local| terrain.get_checked(min + local).clone()));
+	}
+}
+
+// Returns true if the map was changed.
+fn paste(world: &mut World, resources: &mut Resources) -> bool {
+	with_target(world, resources, |editor, target| {
+		let (clipboard, cursor) = match (&editor.clipboard, editor.cursor) {
+			(Some(clipboard), Some(cursor)) => (clipboard, cursor),
+			_ => return false,
+		};
+
+		let mut changes = Vec::new();
+
+		for (local, label) in clipboard.enumerate() {
+			if let Some(position) = target.authored_position(cursor + local) {
+				target.set_terrain(position, label, &mut changes);
+			}
+		}
+
+		editor.unresolved = target.autotile(&mut changes);
+		editor.commit(changes);
+		true
+	})
+}
+
+// Covers the selection with the tile from the palette. Returns true if the map was changed.
+fn fill_selection(world: &mut World, resources: &mut Resources) -> bool {
+	with_target(world, resources, |editor, target| {
+		let (min, max) = match editor.selection {
+			Some(selection) => selection,
+			None => return false,
+		};
+
+		let mut changes = Vec::new();
+
+		for position in rectangle(min, max) {
+			target.set_terrain(position, &editor.label(), &mut changes);
+		}
+
+		editor.unresolved = target.autotile(&mut changes);
+		editor.commit(changes);
+		true
+	})
+}
+
+impl Editor {
+	// Steps through the tiles or prefabs, depending on the tool.
+	pub fn cycle_palette(&mut self, forwards: bool) {
+		let (index, len) = match self.tool {
+			Tool::Object => (&mut self.prefab_index, self.prefabs.len()),
+			_ => (&mut self.label_index, self.labels.len()),
+		};
+
+		if len > 0 {
+			*index = if forwards { (*index + 1) % len } else { (*index + len - 1) % len };
+		}
+	}
+
+	pub fn rotate(&mut self) {
+		self.rotation = match self.rotation {
+			Rotation::Normal => Rotation::Plus90,
+			Rotation::Plus90 => Rotation::Opposite,
+			Rotation::Opposite => Rotation::Minus90,
+			Rotation::Minus90 => Rotation::Normal,
+		};
+	}
+
+	pub fn flip(&mut self) {
+		self.flip = match self.flip {
+			Flip::None => Flip::Horizontal,
+			Flip::Horizontal => Flip::Vertical,
+			Flip::Vertical => Flip::None,
+		};
+	}
+}
+
+// Previews what would be placed under the cursor.
+#[legion::system]
+pub fn render_editor(
+	#[resource] editor: &Editor,
+	#[resource] tileset: &Tileset,
+	#[resource] prefabs: &Prefabs,
+	#[resource] overworld: &Overworld,
+	#[resource] buffers: &mut BufferRenderer,
+) {
+	let cursor = match (editor.enabled, editor.cursor) {
+		(true, Some(cursor)) => cursor,
+		_ => return,
+	};
+
+	let overlay = [1.0, 1.0, 1.0, 0.3];
+	let to_vec = |coord: Coord| cgmath::vec2(coord.x as f32, coord.y as f32);
+
+	match editor.tool {
+		Tool::Object => {
+			let image = editor.prefabs.get(editor.prefab_index).and_then(|name| prefabs.image(name));
+
+			if let Some(image) = image {
+				buffers.render(to_vec(cursor), 0.0, Flip::None, tileset.entity_sprite(image), overlay, [1.0; 3], 1.0);
+			}
+		},
+		_ => {
+			let sprite = tileset.tile_sprite(&editor.labels[editor.label_index]);
+			let start = editor.drag_start.unwrap_or(cursor);
+
+			for cell in rectangle(start, cursor) {
+				buffers.render(to_vec(cell), editor.rotation.to_deg(), editor.flip, sprite, overlay, [1.0; 3], 1.0);
+			}
+
+			// The selection is tinted blue, over the tile that filling it would place.
+			if let (Tool::Select, None, Some((min, max))) = (editor.tool, editor.drag_start, editor.selection) {
+				for position in rectangle(min, max) {
+					let cell = overworld.to_local(position);
+					buffers.render(to_vec(cell), editor.rotation.to_deg(), editor.flip, sprite, SELECTION, [1.0; 3], 1.0);
+				}
+			}
+		},
+	}
+}
+
+// Handles the editor's keys, returning whether the map changed, or `None` for keys the editor doesn't use.
+pub fn key(world: &mut World, resources: &mut Resources, code: winit::event::VirtualKeyCode, ctrl: bool) -> Option<bool> {
+	use winit::event::VirtualKeyCode;
+
+	match code {
+		VirtualKeyCode::Z if ctrl => Some(undo(world, resources, false)),
+		VirtualKeyCode::Y if ctrl => Some(undo(world, resources, true)),
+		VirtualKeyCode::S if ctrl => {
+			save(resources);
+			Some(false)
+		},
+		VirtualKeyCode::C if ctrl => {
+			copy(resources);
+			Some(false)
+		},
+		VirtualKeyCode::V if ctrl => Some(paste(world, resources)),
+		VirtualKeyCode::Return => Some(fill_selection(world, resources)),
+		VirtualKeyCode::Key1 => Some(select_tool(world, resources, Tool::Paint)),
+		VirtualKeyCode::Key2 => Some(select_tool(world, resources, Tool::Fill)),
+		VirtualKeyCode::Key3 => Some(select_tool(world, resources, Tool::Select)),
+		VirtualKeyCode::Key4 => Some(select_tool(world, resources, Tool::Object)),
+		VirtualKeyCode::LBracket | VirtualKeyCode::RBracket => {
+			resources.get_mut::<Editor>().unwrap().cycle_palette(code == VirtualKeyCode::RBracket);
+			Some(false)
+		},
+		VirtualKeyCode::R => {
+			resources.get_mut::<Editor>().unwrap().rotate();
+			Some(false)
+		},
+		VirtualKeyCode::F => {
+			resources.get_mut::<Editor>().unwrap().flip();
+			Some(false)
+		},
+		_ => None,
+	}
+}
@@ -0,0 +1,122 @@
+use legion::*;
+use legion::world::SubWorld;
+use legion::systems::CommandBuffer;
+use grid_2d::Coord;
+use crate::{Egg, Player};
+use crate::weather::WorldClock;
+use crate::rng::{GameRng, Stream};
+
+// The energy an actor needs to take an action, and the ticks a normal action takes.
+pub const ACTION_COST: u32 = 100;
+
+// How much energy an actor gains per tick, relative to `ACTION_COST`. 100 is normal speed.
+#[derive(Copy, Clone)]
+pub struct Speed(pub u32);
+
+#[derive(Copy, Clone)]
+pub struct Energy(pub u32);
+
+// The number of ticks that the player's last action took.
+pub struct Elapsed(pub u32);
+
+// How long it takes the player to heal one point of health.
+const TICKS_PER_HEAL: u64 = 5 * ACTION_COST as u64;
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Health {
+	pub current: u32,
+	pub max: u32,
+}
+
+impl Health {
+	pub fn new(max: u32) -> Self {
+		Self { current: max, max }
+	}
+
+	pub fn is_full(&self) -> bool {
+		self.current >= self.max
+	}
+
+	pub fn fraction(&self) -> f32 {
+		self.current as f32 / self.max as f32
+	}
+}
+
+// The number of times `period` ticks have passed since the last action. Used for anything that
+// happens at a fixed rate, no matter how long each action takes.
+pub fn periods_elapsed(clock: &WorldClock, elapsed: &Elapsed, period: u64) -> u32 {
+	let before = clock.ticks - elapsed.0 as u64;
+	(clock.ticks / period - before / period) as u32
+}
+
+// Converts the cost of an action into the number of ticks it takes someone of the given speed.
+pub fn action_ticks(cost: u32, speed: &Speed) -> u32 {
+	cost * 100 / speed.0.max(1)
+}
+
+// Where the player's last action could be heard from.
+#[derive(Copy, Clone)]
+pub struct Noise {
+	pub origin: Coord,
+	pub radius: i32,
+}
+
+#[legion::system(for_each)]
+pub fn accrue_energy(
+	speed: &Speed, energy: &mut Energy,
+	#[resource] elapsed: &Elapsed,
+) {
+	energy.0 += speed.0 * elapsed.0 / 100;
+}
+
+// Eggs that hear the player are disturbed, and get closer to hatching.
+#[legion::system(for_each)]
+pub fn disturb_eggs(
+	coord: &Coord, _egg: &Egg, energy: &mut Energy,
+	#[resource] noise: &Noise,
+) {
+	if coord.distance2(noise.origin) <= (noise.radius.max(0) as u32).pow(2) {
+		energy.0 += ACTION_COST / 2;
+	}
+}
+
+#[legion::system]
+pub fn regenerate(
+	#[resource] player: &mut Player,
+	#[resource] clock: &WorldClock,
+	#[resource] elapsed: &Elapsed,
+) {
+	let healed = periods_elapsed(clock, elapsed, TICKS_PER_HEAL);
+	player.health.current = (player.health.current + healed).min(player.health.max);
+}
+
+// Eggs act in order of speed, once for every `ACTION_COST` of energy they have. Eggs that are
+// just as fast go in a random order.
+#[legion::system]
+#[read_component(Coord)]
+#[write_component(Egg)]
+#[write_component(Energy)]
+#[read_component(Speed)]
+pub fn step_eggs(
+	world: &mut SubWorld,
+	buffer: &mut CommandBuffer,
+	#[resource] rng: &mut GameRng,
+) {
+	let rng = rng.stream(Stream::Hatching);
+	let mut eggs: Vec<_> = <(Entity, &Coord, &mut Egg, &mut Energy, &Speed)>::query().iter_mut(world).collect();
+
+	// Sorted by position first, so the rolls don't depend on the order eggs happen to be stored in.
+	eggs.sort_by_key(|(_, position, _, _, _)| (position.x, position.y));
+	eggs.sort_by_cached_key(|(_, _, _, _, speed)| (std::cmp::Reverse(speed.0), rng.next()));
+
+	for (entity, _, egg, energy, _) in eggs {
+		while energy.0 >= ACTION_COST && egg.0 > 0 {
+			energy.0 -= ACTION_COST;
+			egg.0 -= 1;
+		}
+
+		if egg.0 == 0 {
+			buffer.remove(*entity);
+		}
+	}
+}
@@ -0,0 +1,659 @@
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use grid_2d::{Coord, Grid, Size};
+use crate::{TileLabel, START};
+use crate::regions::{Regions, Pockets, passable};
+use crate::tileset::{Tileset, Direction, Transform};
+use crate::weather::hash_with;
+use crate::rng::Rng;
+
+// An area of the map, including both corners.
+#[derive(Copy, Clone)]
+pub struct Region {
+	pub min: Coord,
+	pub max: Coord,
+}
+
+impl Region {
+	pub fn around(centre: Coord, radius: i32) -> Self {
+		Self {
+			min: centre - Coord::new(radius, radius),
+			max: centre + Coord::new(radius, radius),
+		}
+	}
+
+	fn contains(&self, position: Coord) -> bool {
+		position.x >= self.min.x && position.x <= self.max.x && position.y >= self.min.y && position.y <= self.max.y
+	}
+}
+
+// Labels that can't be placed anywhere in a region.
+pub struct Forbidden {
+	pub region: Region,
+	pub labels: Vec<String>,
+}
+
+// How many tiles with a label the map has to have.
+pub struct Count {
+	pub label: String,
+	pub min: usize,
+	pub max: usize,
+}
+
+#[derive(Default)]
+pub struct Constraints {
+	// Tiles that are placed before anything else is generated.
+	pub fixed: Vec<(Coord, TileLabel)>,
+	pub forbidden: Vec<Forbidden>,
+	pub counts: Vec<Count>,
+	// Every tile with one of the `connected` labels must be reachable on foot from the spawn.
+	pub spawn: Option<Coord>,
+	pub connected: Vec<String>,
+	// What to do with walkable areas that can't be reached from the spawn.
+	pub pockets: Option<Pockets>,
+}
+
+impl Constraints {
+	// A clearing at the start, at least one cave entrance, and nothing cut off from the player.
+	pub fn playable(tileset: &Tileset) -> Self {
+		let open = ["ground", "snowy_ground"];
+
+		Self {
+			fixed: vec![(START, TileLabel::new("ground"))],
+			forbidden: vec![Forbidden {
+				region: Region::around(START, 2),
+				labels: tileset.tiles.keys().filter(|label| !open.contains(&&label[..])).cloned().collect(),
+			}],
+			counts: vec![
+				Count { label: "cave_enterance".into(), min: 1, max: 8 },
+				Count { label: "egg".into(), min: 0, max: 40 },
+			],
+			spawn: Some(START),
+			connected: vec!["cave_enterance".into()],
+			pockets: Some(Pockets { fill_below: POCKET_SIZE }),
+		}
+	}
+}
+
+// Fills a map with tiles that satisfy the tileset's neighbour rules, picking from the lowest
+// entropy cell first.
+pub struct Generator<'a> {
+	tileset: &'a Tileset,
+	// Every label in every transform that looks different.
+	candidates: Vec<TileLabel>,
+	weights: Vec<u32>,
+	passable: Vec<bool>,
+	// `compatible[direction][a]` is everything that can be placed in `direction` from `a`.
+	compatible: Vec<Vec<CandidateSet>>,
+}
+
+// How big the patterns learned from an example are. Bigger ones copy more of its structure, but
+// need a bigger example to find enough of them.
+pub const PATTERN_SIZE: u32 = 2;
+
+// Pockets smaller than this are generated again, and bigger ones joined up to the spawn.
+const POCKET_SIZE: usize = 12;
+
+// How far around a contradiction is cleared to be generated again.
+const RESET_RADIUS: i32 = 3;
+// How many times an attempt can clear part of the map before giving up on it, for each cell.
+const RESETS_PER_CELL: f32 = 0.05;
+
+// One bit for each candidate.
+struct CandidateSet(Vec<u64>);
+
+impl CandidateSet {
+	fn new(len: usize, contains: impl Fn(usize) -> bool) -> Self {
+		let mut words = vec![0; len.div_ceil(64)];
+
+		for candidate in (0 .. len).filter(|&candidate| contains(candidate)) {
+			words[candidate / 64] |= 1 << (candidate % 64);
+		}
+
+		Self(words)
+	}
+
+	fn contains(&self, candidate: usize) -> bool {
+		self.0[candidate / 64] & (1 << (candidate % 64)) != 0
+	}
+
+	fn add(&mut self, other: &Self) {
+		for (word, other) in self.0.iter_mut().zip(&other.0) {
+			*word |= other;
+		}
+	}
+}
+
+// A cell waiting to be settled, by how many candidates it had left, with ties broken at random.
+type Pending = (usize, u64, (i32, i32));
+
+// What's left to place in each cell during an attempt.
+struct Wave<'g> {
+	generator: &'g Generator<'g>,
+	// What each cell could be before anything was placed, after the constraints.
+	initial: Grid<Vec<usize>>,
+	domains: Grid<Vec<usize>>,
+	// Settled cells for each label.
+	placed: HashMap<String, usize>,
+	maximums: HashMap<String, usize>,
+	queue: VecDeque<Coord>,
+	heap: BinaryHeap<Reverse<Pending>>,
+	rng: Rng,
+	resets: u32,
+	max_resets: u32,
+	// The last area cleared around a contradiction, and its radius.
+	cleared: Option<(Region, i32)>,
+}
+
+impl<'a> Generator<'a> {
+	pub fn new(tileset: &'a Tileset) -> Self {
+		let mut labels: Vec<&String> = tileset.tiles.keys().collect();
+		labels.sort();
+
+		let mut candidates: Vec<TileLabel> = Vec::new();
+
+		for label in labels {
+			let label = TileLabel::new(label);
+
+			for transform in Transform::all() {
+				let candidate = transform.label(&label);
+
+				if !candidates.iter().any(|other| tileset.same_tile(other, &candidate)) {
+					candidates.push(candidate);
+				}
+			}
+		}
+
+		let weights = candidates.iter().map(|candidate| tileset.tiles[&candidate.label].weight).collect();
+		let passable = candidates.iter().map(|candidate| passable(tileset, candidate)).collect();
+
+		let compatible = Direction::ALL.iter()
+			.map(|&direction| candidates.iter()
+				.map(|a| CandidateSet::new(candidates.len(), |b| {
+					let b = &candidates[b];
+					tileset.allows(a, direction, b) || tileset.allows(b, direction.opposite(), a)
+				}))
+				.collect())
+			.collect();
+
+		Self { tileset, candidates, weights, passable, compatible }
+	}
+
+	// Learns what goes together from an example map instead of the tileset's rules, by cutting it
+	// into every `n` by `n` pattern it contains. Each cell is one of those patterns and shows its
+	// top left tile, and neighbouring cells have to agree wherever their patterns overlap.
+	pub fn from_example(tileset: &'a Tileset, example: &Grid<TileLabel>, n: u32) -> Self {
+		let mut patterns: Vec<Grid<TileLabel>> = Vec::new();
+		let mut weights = Vec::new();
+		let mut seen = HashMap::new();
+
+		let within = Size::new((example.width() + 1).saturating_sub(n), (example.height() + 1).saturating_sub(n));
+
+		for corner in within.coord_iter_row_major() {
+			let pattern = Grid::new_fn(Size::new(n, n), |offset| example.get_checked(corner + offset).clone());
+
+			match seen.get(&pattern) {
+				Some(&index) => weights[index] += 1,
+				None => {
+					seen.insert(pattern.clone(), patterns.len());
+					patterns.push(pattern);
+					weights.push(1);
+				},
+			}
+		}
+
+		// Whether `b` can be placed `offset` away from `a`.
+		let agrees = |a: &Grid<TileLabel>, b: &Grid<TileLabel>, offset: Coord| {
+			a.enumerate().all(|(position, label)| b.get(position - offset).is_none_or(|other| other == label))
+		};
+
+		let compatible = Direction::ALL.iter()
+			.map(|&direction| patterns.iter()
+				.map(|a| CandidateSet::new(patterns.len(), |b| agrees(a, &patterns[b], direction.offset())))
+				.collect())
+			.collect();
+
+		let candidates: Vec<TileLabel> = patterns.iter().map(|pattern| pattern.get_checked(Coord::new(0, 0)).clone()).collect();
+		let passable = candidates.iter().map(|candidate| passable(tileset, candidate)).collect();
+
+		Self { tileset, candidates, weights, passable, compatible }
+	}
+
+	// Keeps trying new seeds until a map meets every constraint.
+	pub fn generate(&self, size: Size, constraints: &Constraints, seed: u64, attempts: u32) -> Result<Grid<TileLabel>, String> {
+		let mut reason = String::new();
+
+		for attempt in 0 .. attempts {
+			match self.attempt(size, constraints, hash_with(seed, attempt as u64)) {
+				Ok(map) => return Ok(map),
+				Err(err) => reason = err,
+			}
+		}
+
+		Err(format!("Failed to generate a map in {} attempts, the last because {}", attempts, reason))
+	}
+
+	fn attempt(&self, size: Size, constraints: &Constraints, seed: u64) -> Result<Grid<TileLabel>, String> {
+		let mut initial = Grid::new_fn(size, |_| (0 .. self.candidates.len()).collect::<Vec<usize>>());
+
+		for forbidden in &constraints.forbidden {
+			for (position, domain) in initial.enumerate_mut() {
+				if forbidden.region.contains(position) {
+					domain.retain(|&candidate| !forbidden.labels.contains(&self.candidates[candidate].label));
+				}
+			}
+		}
+
+		for (position, label) in &constraints.fixed {
+			let domain = initial.get_mut(*position)
+				.ok_or_else(|| format!("({}, {}) is outside the map", position.x, position.y))?;
+			domain.retain(|&candidate| self.tileset.same_tile(&self.candidates[candidate], label));
+		}
+
+		let mut wave = Wave {
+			generator: self,
+			// Empty until the first reset, so that nothing's counted as placed.
+			domains: Grid::new_fn(size, |_| Vec::new()),
+			initial,
+			placed: HashMap::new(),
+			maximums: constraints.counts.iter().map(|count| (count.label.clone(), count.max)).collect(),
+			queue: VecDeque::new(),
+			heap: BinaryHeap::new(),
+			rng: Rng::new(seed),
+			resets: 0,
+			max_resets: (size.count() as f32 * RESETS_PER_CELL) as u32,
+			cleared: None,
+		};
+
+		wave.reset(Region { min: Coord::new(0, 0), max: Coord::new(size.x() as i32 - 1, size.y() as i32 - 1) })?;
+
+		loop {
+			wave.collapse()?;
+
+			let map = Grid::new_fn(size, |position| self.candidates[wave.domains.get_checked(position)[0]].clone());
+
+			// Paths are cleared to anything that has to be reachable but isn't, and the area around them
+			// and around pockets too small to join up is generated again.
+			let (blocked, pockets) = self.unreachable(&map, constraints)?;
+
+			if blocked.is_empty() && pockets.is_empty() {
+				self.check_counts(&map, constraints)?;
+				return Ok(map);
+			}
+
+			for &position in &blocked {
+				wave.initial.get_checked_mut(position).retain(|&candidate| self.passable[candidate]);
+			}
+
+			let first = blocked.iter().chain(&pockets).next().copied().unwrap();
+			wave.count_reset(|| format!("({}, {}) is still cut off", first.x, first.y))?;
+
+			for position in blocked.into_iter().chain(pockets) {
+				wave.reset(Region::around(position, 1))?;
+			}
+		}
+	}
+
+	fn check_counts(&self, map: &Grid<TileLabel>, constraints: &Constraints) -> Result<(), String> {
+		for count in &constraints.counts {
+			let placed = map.iter().filter(|label| label.label == count.label).count();
+
+			if placed < count.min || placed > count.max {
+				return Err(format!("there are {} {} tiles, not {} to {}", placed, count.label, count.min, count.max));
+			}
+		}
+
+		Ok(())
+	}
+
+	// The blocked tiles to clear so that everything that has to be reachable from the spawn is, and
+	// the tiles in pockets too small to be worth joining up.
+	fn unreachable(&self, map: &Grid<TileLabel>, constraints: &Constraints) -> Result<(Vec<Coord>, Vec<Coord>), String> {
+		let spawn = match constraints.spawn {
+			Some(spawn) => spawn,
+			None => return Ok((Vec::new(), Vec::new())),
+		};
+
+		let regions = Regions::of_labels(map, self.tileset);
+		let main = regions.region(spawn)
+			.ok_or_else(|| format!("the spawn at ({}, {}) is blocked", spawn.x, spawn.y))?;
+
+		let mut blocked = Vec::new();
+
+		for (position, label) in map.enumerate() {
+			if constraints.connected.contains(&label.label) && regions.region(position) != Some(main) {
+				let path = regions.corridor(position, main)
+					.ok_or_else(|| format!("({}, {}) can't be reached", position.x, position.y))?;
+				blocked.extend(path);
+			}
+		}
+
+		let mut small = Vec::new();
+
+		if let Some(pockets) = constraints.pockets {
+			// Corridors join regions together, so only one is cleared at a time.
+			let mut joined = !blocked.is_empty();
+
+			for pocket in regions.pockets(spawn) {
+				if pocket.len() < pockets.fill_below {
+					small.extend(pocket);
+				} else if !joined {
+					joined = true;
+					blocked.extend(regions.corridor(pocket[0], main).unwrap_or_default());
+				}
+			}
+		}
+
+		Ok((blocked, small))
+	}
+}
+
+impl Wave<'_> {
+	// Settles every cell, clearing the area around any contradiction and trying again.
+	fn collapse(&mut self) -> Result<(), String> {
+		while let Some(Reverse((len, _, (x, y)))) = self.heap.pop() {
+			let position = Coord::new(x, y);
+			let domain = self.domains.get_checked(position);
+
+			if domain.len() != len {
+				if domain.len() > 1 {
+					self.heap.push(Reverse((domain.len(), self.rng.next(), (x, y))));
+				}
+				continue;
+			}
+
+			if len <= 1 {
+				continue;
+			}
+
+			let weights = &self.generator.weights;
+			let total: u64 = domain.iter().map(|&candidate| weights[candidate] as u64).sum();
+			let mut roll = self.rng.next() % total.max(1);
+			let choice = *domain.iter()
+				.find(|&&candidate| {
+					let weight = weights[candidate] as u64;
+					if roll < weight { true } else { roll -= weight; false }
+				})
+				.unwrap_or(&domain[0]);
+
+			*self.domains.get_checked_mut(position) = vec![choice];
+			self.queue.push_back(position);
+
+			let result = self.settle(position).and_then(|_| self.propagate());
+
+			if let Err(contradiction) = result {
+				let region = self.around_contradiction(contradiction)?;
+				self.reset(region)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// Puts a region back to how it started, and constrains it by what's around it.
+	fn reset(&mut self, region: Region) -> Result<(), String> {
+		let mut contradiction = self.try_reset(region);
+
+		while let Err(position) = contradiction {
+			let region = self.around_contradiction(position)?;
+			contradiction = self.try_reset(region);
+		}
+
+		Ok(())
+	}
+
+	// The area to clear around a contradiction, which grows while they keep happening in the same place.
+	fn around_contradiction(&mut self, position: Coord) -> Result<Region, String> {
+		self.count_reset(|| format!("nothing fits at ({}, {})", position.x, position.y))?;
+
+		let radius = match self.cleared {
+			Some((region, radius)) if region.contains(position) => radius + 1,
+			_ => RESET_RADIUS,
+		};
+
+		let region = Region::around(position, radius);
+		self.cleared = Some((region, radius));
+		Ok(region)
+	}
+
+	// Gives up on the attempt once it's cleared part of the map too many times.
+	fn count_reset(&mut self, reason: impl FnOnce() -> String) -> Result<(), String> {
+		self.resets += 1;
+
+		if self.resets > self.max_resets {
+			return Err(format!("{} after {} resets", reason(), self.max_resets));
+		}
+
+		Ok(())
+	}
+
+	// Anything still queued from before is kept, as it may have been left without candidates.
+	fn try_reset(&mut self, region: Region) -> Result<(), Coord> {
+		let size = self.domains.size();
+		let cells: Vec<Coord> = (region.min.y ..= region.max.y)
+			.flat_map(|y| (region.min.x ..= region.max.x).map(move |x| Coord::new(x, y)))
+			.filter(|&position| position.is_valid(size))
+			.collect();
+
+		let candidates = &self.generator.candidates;
+
+		for &position in &cells {
+			if let [settled] = self.domains.get_checked(position)[..] {
+				*self.placed.get_mut(&candidates[settled].label).unwrap() -= 1;
+			}
+		}
+
+		let full: Vec<&String> = self.maximums.iter()
+			.filter(|(label, maximum)| self.placed.get(*label).is_some_and(|placed| placed >= maximum))
+			.map(|(label, _)| label)
+			.collect();
+
+		for &position in &cells {
+			let mut domain = self.initial.get_checked(position).clone();
+			domain.retain(|&candidate| !full.contains(&&candidates[candidate].label));
+			*self.domains.get_checked_mut(position) = domain;
+		}
+
+		// Every cell is counted before giving up on a contradiction, as they're all taken off again when
+		// it's cleared.
+		let mut contradiction = Ok(());
+
+		for &position in &cells {
+			contradiction = contradiction.and(self.settle(position));
+
+			let len = self.domains.get_checked(position).len();
+			self.heap.push(Reverse((len, self.rng.next(), (position.x, position.y))));
+
+			// The cells around the region constrain it, and it constrains them.
+			self.queue.push_back(position);
+
+			for &direction in &Direction::ALL {
+				let neighbour = position + direction.offset();
+
+				if neighbour.is_valid(size) && !region.contains(neighbour) {
+					self.queue.push_back(neighbour);
+				}
+			}
+		}
+
+		contradiction?;
+		self.propagate()
+	}
+
+	// Counts a cell once it's down to a single candidate, and stops placing labels that have
+	// reached their maximum.
+	fn settle(&mut self, position: Coord) -> Result<(), Coord> {
+		let domain = self.domains.get_checked(position);
+
+		let label = match domain.len() {
+			0 => return Err(position),
+			1 => self.generator.candidates[domain[0]].label.clone(),
+			_ => return Ok(()),
+		};
+
+		let placed = self.placed.entry(label.clone()).or_insert(0);
+		*placed += 1;
+
+		if Some(&*placed) != self.maximums.get(&label) {
+			return Ok(());
+		}
+
+		let candidates = &self.generator.candidates;
+
+		let mut narrowed = Vec::new();
+
+		for (other, domain) in self.domains.enumerate_mut() {
+			if domain.len() > 1 && domain.iter().any(|&candidate| candidates[candidate].label == label) {
+				domain.retain(|&candidate| candidates[candidate].label != label);
+				narrowed.push(other);
+			}
+		}
+
+		// Cells left with one candidate are counted too, and ones that had nothing else left are found
+		// when they're propagated.
+		for other in narrowed {
+			self.queue.push_back(other);
+
+			if self.domains.get_checked(other).len() == 1 {
+				self.settle(other)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// Removes candidates that nothing next to them fits with, until nothing changes.
+	fn propagate(&mut self) -> Result<(), Coord> {
+		while let Some(position) = self.queue.pop_front() {
+			if self.domains.get_checked(position).is_empty() {
+				return Err(position);
+			}
+
+			for &direction in &Direction::ALL {
+				let neighbour = position + direction.offset();
+
+				let neighbour_domain = match self.domains.get(neighbour) {
+					Some(neighbour_domain) => neighbour_domain,
+					None => continue,
+				};
+
+				let compatible = &self.generator.compatible[direction as usize];
+				let mut supported = CandidateSet::new(self.generator.candidates.len(), |_| false);
+
+				for &tile in self.domains.get_checked(position) {
+					supported.add(&compatible[tile]);
+				}
+
+				let remaining: Vec<usize> = neighbour_domain.iter()
+					.filter(|&&candidate| supported.contains(candidate))
+					.cloned()
+					.collect();
+
+				if remaining.len() == neighbour_domain.len() {
+					continue;
+				}
+
+				*self.domains.get_checked_mut(neighbour) = remaining;
+				self.settle(neighbour)?;
+				self.queue.push_back(neighbour);
+			}
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+	use super::*;
+	use crate::validate::validate;
+
+	fn tileset() -> Tileset {
+		Tileset::load(&Path::new(crate::tileset::ASSET_DIRECTORY).join("alien.ron"))
+	}
+
+	#[test]
+	fn playable_map_has_one_region() {
+		let tileset = tileset();
+		let map = Generator::new(&tileset).generate(Size::new(32, 32), &Constraints::playable(&tileset), 1, 100).unwrap();
+
+		assert_eq!(Regions::of_labels(&map, &tileset).count(), 1);
+		assert!(validate(&map, &tileset).is_empty());
+	}
+
+	#[test]
+	fn fixed_tiles_are_kept() {
+		let tileset = tileset();
+		let cave = TileLabel::new("cave");
+		let constraints = Constraints { fixed: vec![(Coord::new(5, 5), cave.clone())], ..Default::default() };
+		let map = Generator::new(&tileset).generate(Size::new(16, 16), &constraints, 1, 100).unwrap();
+
+		assert!(tileset.same_tile(map.get_checked(Coord::new(5, 5)), &cave));
+	}
+
+	#[test]
+	fn forbidden_labels_are_left_out() {
+		let tileset = tileset();
+		let region = Region::around(Coord::new(8, 8), 3);
+		let labels = vec!["ground".to_string(), "snowy_ground".to_string()];
+		let constraints = Constraints {
+			forbidden: vec![Forbidden { region, labels: labels.clone() }],
+			..Default::default()
+		};
+		let map = Generator::new(&tileset).generate(Size::new(16, 16), &constraints, 1, 100).unwrap();
+
+		assert!(map.enumerate().all(|(position, label)| !region.contains(position) || !labels.contains(&label.label)));
+	}
+
+	#[test]
+	fn counts_are_kept_within_range() {
+		let tileset = tileset();
+		let constraints = Constraints {
+			counts: vec![
+				Count { label: "cave_enterance".into(), min: 1, max: 2 },
+				Count { label: "egg".into(), min: 0, max: 0 },
+			],
+			..Default::default()
+		};
+		let map = Generator::new(&tileset).generate(Size::new(16, 16), &constraints, 1, 100).unwrap();
+		let count = |label: &str| map.iter().filter(|tile| tile.label == label).count();
+
+		assert!((1 ..= 2).contains(&count("cave_enterance")));
+		assert_eq!(count("egg"), 0);
+	}
+
+	#[test]
+	fn impossible_counts_fail() {
+		let tileset = tileset();
+		let constraints = Constraints {
+			counts: vec![Count { label: "rocks".into(), min: 100, max: 100 }],
+			..Default::default()
+		};
+
+		assert!(Generator::new(&tileset).generate(Size::new(8, 8), &constraints, 1, 3).is_err());
+	}
+
+	#[test]
+	fn walled_off_entrances_fail() {
+		let tileset = tileset();
+		let walkable: Vec<String> = tileset.tiles.keys()
+			.filter(|label| passable(&tileset, &TileLabel::new(label)))
+			.cloned()
+			.collect();
+
+		// A wall down the middle that nothing can get through, with the entrances on the far side.
+		let constraints = Constraints {
+			forbidden: vec![
+				Forbidden { region: Region { min: Coord::new(8, 0), max: Coord::new(8, 15) }, labels: walkable },
+				Forbidden { region: Region { min: Coord::new(0, 0), max: Coord::new(8, 15) }, labels: vec!["cave_enterance".into()] },
+			],
+			counts: vec![Count { label: "cave_enterance".into(), min: 1, max: 4 }],
+			spawn: Some(START),
+			connected: vec!["cave_enterance".into()],
+			..Default::default()
+		};
+
+		assert!(Generator::new(&tileset).generate(Size::new(16, 16), &constraints, 1, 3).is_err());
+	}
+}
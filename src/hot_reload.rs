@@ -0,0 +1,276 @@
+use std::path::PathBuf;
+use std::time::{Instant, Duration, SystemTime};
+use legion::*;
+use grid_2d::{Coord, Grid};
+use crate::{Tile, TileTag, Visibility, Player, Image};
+use crate::chunks::Overworld;
+use crate::prefabs::Prefabs;
+use crate::tileset::Tileset;
+use crate::renderer::{Renderer, BufferRenderer};
+use crate::minimap::Minimap;
+use crate::light::Light;
+use crate::level::{self, Levels};
+use crate::map::{MapFile, load_map};
+use crate::editor::Editor;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, PartialEq)]
+enum AssetKind {
+	Atlas,
+	Tileset,
+	Map,
+}
+
+struct WatchedFile {
+	path: PathBuf,
+	kind: AssetKind,
+	modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+	fn new(path: PathBuf, kind: AssetKind) -> Self {
+		let modified = modified(&path);
+		Self { path, kind, modified }
+	}
+}
+
+fn modified(path: &std::path::Path) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+// Polls the modification times of the tileset, its atlas images and the map, reloading them in
+// place when they change.
+pub struct AssetWatcher {
+	tileset_path: PathBuf,
+	map_path: PathBuf,
+	files: Vec<WatchedFile>,
+	last_poll: Instant,
+}
+
+impl AssetWatcher {
+	pub fn new(tileset_path: PathBuf, map_path: PathBuf, tileset: &Tileset) -> Self {
+		let mut watcher = Self {
+			tileset_path, map_path,
+			files: Vec::new(),
+			last_poll: Instant::now(),
+		};
+		watcher.watch(tileset);
+		watcher
+	}
+
+	fn watch(&mut self, tileset: &Tileset) {
+		self.files = tileset.atlases().iter()
+			.map(|atlas| WatchedFile::new(atlas.image_path(), AssetKind::Atlas))
+			.chain(std::iter::once(WatchedFile::new(self.tileset_path.clone(), AssetKind::Tileset)))
+			.chain(std::iter::once(WatchedFile::new(self.map_path.clone(), AssetKind::Map)))
+			.collect();
+	}
+
+	fn changed(&mut self) -> Vec<AssetKind> {
+		let mut changed = Vec::new();
+
+		for file in &mut self.files {
+			let modified = modified(&file.path);
+
+			if modified != file.modified {
+				file.modified = modified;
+				if !changed.contains(&file.kind) {
+					changed.push(file.kind);
+				}
+			}
+		}
+
+		changed
+	}
+
+	// Returns whether the map or its sprites were reloaded, in which case visibility needs to be
+	// recalculated and the terrain rerendered.
+	pub fn poll(&mut self, world: &mut World, resources: &mut Resources, renderer: &mut Renderer) -> bool {
+		if self.last_poll.elapsed() < POLL_INTERVAL {
+			return false;
+		}
+		self.last_poll = Instant::now();
+
+		let changed = self.changed();
+
+		if changed.is_empty() {
+			return false;
+		}
+
+		let new_tileset = if changed.contains(&AssetKind::Tileset) {
+			match Tileset::try_load(&self.tileset_path) {
+				Ok(tileset) => Some(tileset),
+				Err(err) => {
+					eprintln!("{}", err);
+					None
+				}
+			}
+		} else {
+			None
+		};
+
+		let reload_atlases = changed.contains(&AssetKind::Atlas) || new_tileset.is_some();
+		let reload_sprites = new_tileset.is_some();
+
+		let map = if changed.contains(&AssetKind::Map) {
+			match load_map(&self.map_path) {
+				Ok(map) => Some(map),
+				Err(err) => {
+					eprintln!("{}", err);
+					None
+				}
+			}
+		} else {
+			None
+		};
+
+		// Everything is checked against the new tileset before any of it is swapped in, so a bad
+		// edit leaves the old assets in place.
+		{
+			let current = resources.get::<Tileset>().unwrap();
+			let tileset = new_tileset.as_ref().unwrap_or(&current);
+
+			if let Err(err) = check(world, resources, tileset, map.as_ref()) {
+				eprintln!("{}, not reloading the tileset or map", err);
+				return false;
+			}
+
+			if reload_atlases {
+				let mut buffers = resources.get_mut::<BufferRenderer>().unwrap();
+
+				match renderer.reload_atlases(tileset, &mut buffers) {
+					Ok(()) => println!("Reloaded atlases"),
+					Err(err) => {
+						eprintln!("{}, not reloading the tileset or map", err);
+						return false;
+					}
+				}
+			}
+		}
+
+		if let Some(tileset) = new_tileset {
+			// The set of atlas images may have changed as well.
+			self.watch(&tileset);
+			resources.get_mut::<Editor>().unwrap().reload(&tileset);
+			resources.insert(tileset);
+		}
+
+		match map {
+			Some(map) => {
+				// Caves are generated from the surface's entrances, so they're thrown away too.
+				level::return_to_surface(world, resources);
+				let tileset = resources.get::<Tileset>().unwrap();
+
+				// Entities are spawned from the map, so they need to be recreated as well.
+				world.clear();
+				let (map, _) = {
+					let mut overworld = resources.get_mut::<Overworld>().unwrap();
+					overworld.set_authored(map);
+					overworld.build_window(world, &tileset, &resources.get().unwrap())
+				};
+				drop(tileset);
+				replace_map(resources, map);
+				println!("Reloaded map");
+				true
+			},
+			None => {
+				if reload_sprites {
+					let tileset = resources.get::<Tileset>().unwrap();
+					let mut map = resources.get_mut::<Grid<Tile>>().unwrap();
+					let mut levels = resources.get_mut::<Levels>().unwrap();
+
+					for map in std::iter::once(&mut *map).chain(levels.stored_maps_mut()) {
+						map.iter_mut().for_each(|tile| {
+							tile.sprite = tileset.tile_sprite(tile.tag.label());
+							tile.properties = tileset.tile_properties(tile.tag.label());
+						});
+					}
+					println!("Reloaded tileset");
+				}
+
+				// The terrain's uvs change with the size of the atlases, as well as with the sprites.
+				reload_sprites || reload_atlases
+			}
+		}
+	}
+}
+
+// Checks that everything in use, or about to be, has a tile or sprite in `tileset`, that every tile
+// can be loaded, and that the new map only places prefabs that exist.
+fn check(world: &World, resources: &Resources, tileset: &Tileset, map: Option<&MapFile>) -> Result<(), String> {
+	let levels = resources.get::<Levels>().unwrap();
+	let prefabs = resources.get::<Prefabs>().unwrap();
+
+	// Any tile can be painted from the editor's palette, not just the ones in use.
+	for (label, definition) in &tileset.tiles {
+		let terrain = definition.terrain.as_deref().unwrap_or(label);
+
+		if !TileTag::from_label(terrain).is_some_and(|tag| tileset.tiles.contains_key(tag.label())) {
+			return Err(format!("'{}' has the terrain '{}', which isn't a tile the game knows", label, terrain));
+		}
+
+		if let Some(prefab) = definition.spawn.as_ref().filter(|prefab| !prefabs.contains(prefab)) {
+			return Err(format!("'{}' spawns '{}', but there is no prefab by that name", label, prefab));
+		}
+	}
+
+	let missing_tile = match map {
+		Some(map) => map.terrain.iter()
+			.map(|label| label.label.clone())
+			.find(|label| !tileset.tiles.contains_key(label)),
+		None => std::iter::once(&*resources.get::<Grid<Tile>>().unwrap()).chain(levels.stored_maps())
+			.flat_map(|map| map.iter())
+			.map(|tile| tile.tag.label().to_string())
+			.find(|label| !tileset.tiles.contains_key(label)),
+	};
+
+	if let Some(label) = missing_tile {
+		return Err(format!("Tileset does not contain '{}'", label));
+	}
+
+	let inventory = resources.get::<Player>().unwrap().inventory.items.iter()
+		.filter_map(|item| prefabs.image(item).map(str::to_string))
+		.collect::<Vec<_>>();
+
+	let missing_sprite = std::iter::once(world).chain(levels.stored_worlds())
+		.flat_map(|world| <&Image>::query().iter(world).map(|image| image.0.clone()).collect::<Vec<_>>())
+		.chain(inventory)
+		.chain(std::iter::once("person".to_string()))
+		.find(|label| !tileset.sprites.contains_key(label));
+
+	if let Some(label) = missing_sprite {
+		return Err(format!("Tileset does not contain a sprite for '{}'", label));
+	}
+
+	if let Some(map) = map {
+		if let Some(object) = map.objects.iter().find(|object| !prefabs.contains(&object.prefab)) {
+			return Err(format!("There is no prefab named '{}'", object.prefab));
+		}
+	}
+
+	Ok(())
+}
+
+// Swaps in a new map, keeping the explored state and the player's position where they still fit.
+fn replace_map(resources: &mut Resources, map: Grid<Tile>) {
+	let old_vis_map = resources.remove::<Grid<Visibility>>().unwrap();
+
+	let vis_map = Grid::new_fn(map.size(), |coord| match old_vis_map.get(coord) {
+		Some(Visibility::Invisible) | None => Visibility::Invisible,
+		Some(_) => Visibility::PreviouslyVisible,
+	});
+
+	{
+		let mut player = resources.get_mut::<Player>().unwrap();
+		player.position = Coord {
+			x: player.position.x.min(map.width() as i32 - 1).max(0),
+			y: player.position.y.min(map.height() as i32 - 1).max(0),
+		};
+	}
+
+	resources.insert(Minimap::new(map.size()));
+	resources.insert(Grid::new_copy(map.size(), Light::default()));
+	resources.insert(vis_map);
+	resources.insert(map);
+}
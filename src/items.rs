@@ -0,0 +1,172 @@
+use legion::*;
+use legion::systems::CommandBuffer;
+use grid_2d::Coord;
+use crate::{Player, Camera};
+use crate::energy::{Elapsed, ACTION_COST};
+use crate::prefabs::{Prefabs, Prefab};
+use crate::renderer::BufferRenderer;
+use crate::tileset::Tileset;
+
+#[derive(Copy, Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
+pub enum ItemKind {
+	Ration,
+	Flare,
+}
+
+// An item lying on the ground, which can be picked up. What it does is looked up from its prefab.
+#[derive(Copy, Clone)]
+pub struct Item;
+
+// Ticks left until a light burns out.
+#[derive(Copy, Clone)]
+pub struct Fuel(pub u32);
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Inventory {
+	// The prefabs that the items were picked up from.
+	pub items: Vec<String>,
+	pub capacity: usize,
+	pub selected: usize,
+}
+
+impl Inventory {
+	pub fn new(capacity: usize) -> Self {
+		Self {
+			items: Vec::new(),
+			capacity,
+			selected: 0,
+		}
+	}
+
+	pub fn select(&mut self, slot: usize) {
+		if slot < self.capacity {
+			self.selected = slot;
+		}
+	}
+
+	// Drops anything that isn't an item any more, such as after prefabs.ron has been edited, so
+	// that older saves still load.
+	pub fn forget_unknown(&mut self, prefabs: &Prefabs, tileset: &Tileset) {
+		self.items.retain(|name| {
+			let known = prefabs.contains(name)
+				&& prefabs.item_kind(name).is_some()
+				&& prefabs.image(name).is_some_and(|image| tileset.sprites.contains_key(image));
+
+			if !known {
+				eprintln!("'{}' isn't an item any more, so it's been taken out of the inventory", name);
+			}
+			known
+		});
+	}
+
+	fn take_selected(&mut self) -> Option<String> {
+		if self.selected < self.items.len() {
+			Some(self.items.remove(self.selected))
+		} else {
+			None
+		}
+	}
+}
+
+// Whether the inventory screen is open.
+#[derive(Default)]
+pub struct InventoryScreen(pub bool);
+
+// Each of these returns the energy cost of the action, or `None` if it couldn't be done.
+pub fn pick_up(world: &mut World, resources: &mut Resources) -> Option<u32> {
+	let mut player = resources.get_mut::<Player>().unwrap();
+
+	if player.inventory.items.len() >= player.inventory.capacity {
+		return None;
+	}
+
+	let (entity, prefab) = <(Entity, &Coord, &Item, &Prefab)>::query().iter(world)
+		.find(|(_, coord, _, _)| **coord == player.position)
+		.map(|(entity, _, _, prefab)| (*entity, prefab.0.clone()))?;
+
+	world.remove(entity);
+	player.inventory.items.push(prefab);
+	Some(ACTION_COST)
+}
+
+pub fn drop_item(world: &mut World, resources: &mut Resources) -> Option<u32> {
+	let mut player = resources.get_mut::<Player>().unwrap();
+	let prefab = player.inventory.take_selected()?;
+	resources.get::<Prefabs>().unwrap().spawn(world, player.position, &prefab);
+	Some(ACTION_COST)
+}
+
+pub fn use_item(world: &mut World, resources: &mut Resources) -> Option<u32> {
+	let mut player = resources.get_mut::<Player>().unwrap();
+	let prefabs = resources.get::<Prefabs>().unwrap();
+	let prefab = player.inventory.take_selected()?;
+
+	// Anything that isn't an item is thrown away, as `forget_unknown` does when a save is loaded.
+	match prefabs.item_kind(&prefab) {
+		Some(ItemKind::Ration) => {
+			player.health.current = (player.health.current + 8).min(player.health.max);
+		},
+		Some(ItemKind::Flare) => {
+			prefabs.spawn(world, player.position, "lit_flare");
+		},
+		None => {
+			eprintln!("'{}' isn't an item any more, so it's been taken out of the inventory", prefab);
+			return None;
+		},
+	}
+
+	Some(ACTION_COST)
+}
+
+#[legion::system(for_each)]
+pub fn burn_out(
+	entity: &Entity, fuel: &mut Fuel,
+	buffer: &mut CommandBuffer,
+	#[resource] elapsed: &Elapsed,
+) {
+	fuel.0 = fuel.0.saturating_sub(elapsed.0);
+
+	if fuel.0 == 0 {
+		buffer.remove(*entity);
+	}
+}
+
+// A row of slots along the bottom of the window, with the items' sprites in them.
+#[legion::system]
+pub fn render_inventory(
+	#[resource] screen: &InventoryScreen,
+	#[resource] player: &Player,
+	#[resource] camera: &Camera,
+	#[resource] prefabs: &Prefabs,
+	#[resource] tileset: &Tileset,
+	#[resource] buffers: &mut BufferRenderer,
+) {
+	if !screen.0 {
+		return;
+	}
+
+	// In half pixels, like the minimap.
+	let slot_size = 48.0;
+	let gap = 12.0;
+	let inventory = &player.inventory;
+	let width = inventory.capacity as f32 * (slot_size + gap) - gap;
+	let bottom = -camera.window_size.y + slot_size + gap * 2.0;
+
+	buffers.render_minimap_quad(
+		cgmath::vec2(0.0, bottom), cgmath::vec2(width / 2.0 + gap, slot_size / 2.0 + gap), [0.1, 0.1, 0.15, 0.9], false,
+	);
+
+	for slot in 0 .. inventory.capacity {
+		let center = cgmath::vec2(-width / 2.0 + slot_size / 2.0 + slot as f32 * (slot_size + gap), bottom);
+
+		if slot == inventory.selected {
+			buffers.render_minimap_quad(center, cgmath::vec2(slot_size / 2.0 + 4.0, slot_size / 2.0 + 4.0), [1.0, 1.0, 0.3, 1.0], false);
+		}
+
+		buffers.render_minimap_quad(center, cgmath::vec2(slot_size / 2.0, slot_size / 2.0), [0.25, 0.25, 0.3, 1.0], false);
+
+		if let Some(image) = inventory.items.get(slot).and_then(|prefab| prefabs.image(prefab)) {
+			buffers.render_ui_sprite(center, slot_size / 2.0, tileset.entity_sprite(image), camera);
+		}
+	}
+}
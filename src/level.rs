@@ -0,0 +1,249 @@
+use std::collections::HashMap;
+use std::path::Path;
+use legion::*;
+use grid_2d::{Coord, Grid, Size};
+use crate::{Tile, TileTag, TileLabel, Visibility, Player, Camera, load_world};
+use crate::tileset::Tileset;
+use crate::light::Light;
+use crate::minimap::Minimap;
+use crate::weather::hash_with;
+use crate::chunks::{Overworld, ChunkFile};
+use crate::prefabs::Prefabs;
+use crate::regions::{self, Pockets};
+use crate::rng::GameRng;
+use crate::tween::PlayerTween;
+use crate::energy::Noise;
+
+const CAVE_WIDTH: u32 = 60;
+const CAVE_HEIGHT: u32 = 60;
+const CAVE_POCKET_SIZE: usize = 12;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum LevelId {
+	Surface,
+	// Each cave entrance on the surface leads to its own cave. Entrances are in absolute coordinates,
+	// as the overworld's window moves around.
+	Cave(Coord),
+}
+
+// Everything that belongs to a level that isn't currently being played.
+struct StoredLevel {
+	world: World,
+	map: Grid<Tile>,
+	vis_map: Grid<Visibility>,
+	light_map: Grid<Light>,
+	minimap: Minimap,
+}
+
+pub struct Levels {
+	pub current: LevelId,
+	stored: HashMap<LevelId, StoredLevel>,
+}
+
+impl Default for Levels {
+	fn default() -> Self {
+		Self {
+			current: LevelId::Surface,
+			stored: HashMap::new(),
+		}
+	}
+}
+
+impl Levels {
+	// Lets the tileset be hot-reloaded while levels are stored.
+	pub fn stored_maps_mut(&mut self) -> impl Iterator<Item = &mut Grid<Tile>> {
+		self.stored.values_mut().map(|level| &mut level.map)
+	}
+
+	// Lets a new tileset be checked against what's stored before it's swapped in.
+	pub fn stored_maps(&self) -> impl Iterator<Item = &Grid<Tile>> {
+		self.stored.values().map(|level| &level.map)
+	}
+
+	pub fn stored_worlds(&self) -> impl Iterator<Item = &World> {
+		self.stored.values().map(|level| &level.world)
+	}
+}
+
+// Called after the player moves. Stepping onto an entrance takes them down into its cave, and
+// stepping onto the way out of a cave takes them back to the surface.
+pub fn use_entrance(world: &mut World, resources: &mut Resources) {
+	let position = resources.get::<Player>().unwrap().position;
+	let current = resources.get::<Levels>().unwrap().current;
+
+	if !matches!(resources.get::<Grid<Tile>>().unwrap().get_checked(position).tag, TileTag::CaveEnterance) {
+		return;
+	}
+
+	match current {
+		LevelId::Surface => {
+			let entrance = resources.get::<Overworld>().unwrap().to_absolute(position);
+			change_level(world, resources, LevelId::Cave(entrance), cave_exit());
+		},
+		LevelId::Cave(entrance) => {
+			let arrival = resources.get::<Overworld>().unwrap().to_local(entrance);
+			change_level(world, resources, LevelId::Surface, arrival);
+		},
+	}
+}
+
+// Brings the player back up to the surface, keeping the cave they were in.
+pub fn leave_cave(world: &mut World, resources: &mut Resources) {
+	let current = resources.get::<Levels>().unwrap().current;
+
+	if let LevelId::Cave(entrance) = current {
+		let arrival = resources.get::<Overworld>().unwrap().to_local(entrance);
+		change_level(world, resources, LevelId::Surface, arrival);
+	}
+}
+
+// Used when the surface map is replaced, as the caves were generated from its entrances.
+pub fn return_to_surface(world: &mut World, resources: &mut Resources) {
+	leave_cave(world, resources);
+	resources.get_mut::<Levels>().unwrap().stored.clear();
+}
+
+// Writes out every cave that's been visited, so that whatever was left in them is still there
+// next time.
+pub fn save_caves(resources: &Resources) {
+	let levels = resources.get::<Levels>().unwrap();
+	let overworld = resources.get::<Overworld>().unwrap();
+
+	for (id, level) in &levels.stored {
+		if let LevelId::Cave(entrance) = id {
+			ChunkFile::save(&level.world, &level.map, &level.vis_map, Coord::new(0, 0), level.map.size())
+				.write(&overworld.cave_path(*entrance));
+		}
+	}
+}
+
+fn change_level(world: &mut World, resources: &mut Resources, target: LevelId, arrival: Coord) {
+	let outgoing = StoredLevel {
+		world: std::mem::take(world),
+		map: resources.remove().unwrap(),
+		vis_map: resources.remove().unwrap(),
+		light_map: resources.remove().unwrap(),
+		minimap: resources.remove().unwrap(),
+	};
+
+	let incoming = {
+		let mut levels = resources.get_mut::<Levels>().unwrap();
+		let current = levels.current;
+		levels.stored.insert(current, outgoing);
+		levels.current = target;
+		levels.stored.remove(&target)
+	};
+
+	let mut level = incoming.unwrap_or_else(|| match target {
+		LevelId::Cave(entrance) => {
+			let path = resources.get::<Overworld>().unwrap().cave_path(entrance);
+
+			if path.exists() {
+				load_cave(&path, &resources.get().unwrap(), &resources.get().unwrap())
+			} else {
+				generate_cave(
+					entrance, resources.get::<GameRng>().unwrap().seed, &resources.get().unwrap(), &resources.get().unwrap(),
+				)
+			}
+		},
+		LevelId::Surface => panic!("The surface is always stored while underground"),
+	});
+
+	// The renderer's minimap texture still holds the old level.
+	level.minimap.dirty = true;
+
+	*world = level.world;
+	resources.insert(level.map);
+	resources.insert(level.vis_map);
+	resources.insert(level.light_map);
+	resources.insert(level.minimap);
+
+	// The view and the noise of arriving jump along with the player, like when the overworld is
+	// recentred.
+	let offset = arrival - std::mem::replace(&mut resources.get_mut::<Player>().unwrap().position, arrival);
+	resources.get_mut::<PlayerTween>().unwrap().0.translate(offset);
+	resources.get_mut::<Noise>().unwrap().origin += offset;
+	resources.get_mut::<Camera>().unwrap().position += cgmath::vec2(offset.x as f32, offset.y as f32);
+}
+
+fn load_cave(path: &Path, tileset: &Tileset, prefabs: &Prefabs) -> StoredLevel {
+	let mut world = World::default();
+	let (map, vis_map) = ChunkFile::read(path).load(&mut world, tileset, prefabs, Coord::new(0, 0));
+
+	StoredLevel {
+		world,
+		vis_map,
+		light_map: Grid::new_copy(map.size(), Light::default()),
+		minimap: Minimap::new(map.size()),
+		map,
+	}
+}
+
+fn cave_exit() -> Coord {
+	Coord::new(CAVE_WIDTH as i32 / 2, CAVE_HEIGHT as i32 / 2)
+}
+
+// Carves out a cave with a cellular automaton, seeded by the world and the position of its entrance.
+fn generate_cave(entrance: Coord, world_seed: u64, tileset: &Tileset, prefabs: &Prefabs) -> StoredLevel {
+	let seed = hash_with(world_seed, ((entrance.x as u64) << 32) | entrance.y as u32 as u64);
+	let roll = |coord: Coord, salt: u64| hash_with(hash_with(seed, salt), ((coord.x as u64) << 32) | coord.y as u32 as u64) % 100;
+
+	let size = Size::new(CAVE_WIDTH, CAVE_HEIGHT);
+	let is_edge = |Coord { x, y }: Coord| {
+		x == 0 || y == 0 || x == CAVE_WIDTH as i32 - 1 || y == CAVE_HEIGHT as i32 - 1
+	};
+
+	let mut walls = Grid::new_fn(size, |coord| is_edge(coord) || roll(coord, 0) < 45);
+
+	for _ in 0 .. 4 {
+		walls = Grid::new_fn(size, |coord| {
+			let neighbours = (-1 ..= 1)
+				.flat_map(|x| (-1 ..= 1).map(move |y| Coord::new(x, y)))
+				.filter(|offset| *offset != Coord::new(0, 0))
+				.filter(|offset| *walls.get(coord + *offset).unwrap_or(&true))
+				.count();
+
+			is_edge(coord) || neighbours >= 5 || (neighbours == 0 && roll(coord, 1) < 50)
+		});
+	}
+
+	// Keep the area around the way out clear.
+	let exit = cave_exit();
+
+	let mut labels = Grid::new_fn(size, |coord| {
+		let label = if coord == exit {
+			"cave_enterance"
+		} else if coord.distance2(exit) <= 4 {
+			"cave"
+		} else if *walls.get_checked(coord) {
+			"cave_wall"
+		} else {
+			match roll(coord, 2) {
+				0 => "egg",
+				1 ..= 4 => "goop",
+				_ => "cave",
+			}
+		};
+
+		TileLabel {
+			label: label.to_string(),
+			rotation: Default::default(),
+			flip: Default::default(),
+			subsection: (0, 0),
+		}
+	});
+
+	// Anything too small to explore is filled in, and the rest joined up to the way out.
+	regions::tidy(&mut labels, tileset, exit, Pockets { fill_below: CAVE_POCKET_SIZE }, "cave", "cave_wall");
+
+	let mut world = World::default();
+	let map = load_world(&mut world, labels, tileset, prefabs);
+
+	StoredLevel {
+		world,
+		vis_map: Grid::new_grid_map_ref(&map, |_| Visibility::Invisible),
+		light_map: Grid::new_copy(map.size(), Light::default()),
+		minimap: Minimap::new(map.size()),
+		map,
+	}
+}
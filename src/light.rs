@@ -0,0 +1,120 @@
+use legion::*;
+use legion::world::SubWorld;
+use grid_2d::{Coord, Grid};
+use crate::{Tile, Player};
+use crate::weather::Sky;
+
+// How much light a dark tile needs before the player can see it.
+const VISIBILITY_THRESHOLD: f32 = 0.1;
+
+// Light sources further than this from the player can't light up anything the player can see.
+const MAX_LIGHT_RADIUS: i32 = 8;
+
+#[derive(Copy, Clone)]
+pub struct LightSource {
+	pub radius: i32,
+	// Light at the source, fading out linearly towards the radius.
+	pub colour: [f32; 3],
+}
+
+// The player's light, which moves with them.
+pub struct Lantern(pub LightSource);
+
+#[derive(Copy, Clone, Default)]
+pub struct Light(pub [f32; 3]);
+
+impl Light {
+	fn add(&mut self, colour: [f32; 3], strength: f32) {
+		for (channel, colour) in self.0.iter_mut().zip(&colour) {
+			*channel += colour * strength;
+		}
+	}
+
+	pub fn level(&self) -> f32 {
+		self.0.iter().cloned().fold(0.0, f32::max)
+	}
+
+	pub fn can_see(&self, tile: &Tile) -> bool {
+		!tile.tag.is_dark() || self.level() >= VISIBILITY_THRESHOLD
+	}
+
+	// Multiplied with the tile's texture. Dark tiles are only lit by light sources, while tiles
+	// outside are also lit by the sky.
+	pub fn tint(&self, tile: &Tile, ambient: [f32; 3]) -> [f32; 3] {
+		let mut tint = self.0;
+
+		if !tile.tag.is_dark() {
+			for (channel, ambient) in tint.iter_mut().zip(&ambient) {
+				*channel = ambient + *channel * 0.5;
+			}
+		}
+
+		[tint[0].min(1.25), tint[1].min(1.25), tint[2].min(1.25)]
+	}
+}
+
+fn cast_light(source: Coord, light: LightSource, map: &Grid<Tile>, light_map: &mut Grid<Light>) {
+	let radius = light.radius;
+
+	for x in source.x - radius ..= source.x + radius {
+		for y in source.y - radius ..= source.y + radius {
+			let coord = Coord { x, y };
+
+			let distance = (source.distance2(coord) as f32).sqrt();
+
+			if distance > radius as f32 {
+				continue;
+			}
+
+			if let Some(cell) = light_map.get_mut(coord) {
+				// Tiles that block sight are lit, but stop the light from going any further.
+				let is_lit = line_drawing::Bresenham::new((x, y), (source.x, source.y))
+					.skip(1)
+					.all(|(x, y)| !map.get_checked(Coord { x, y }).tag.blocks_sight());
+
+				if is_lit {
+					cell.add(light.colour, 1.0 - distance / (radius as f32 + 1.0));
+				}
+			}
+		}
+	}
+}
+
+#[legion::system]
+#[read_component(Coord)]
+#[read_component(LightSource)]
+pub fn update_lighting(
+	world: &mut SubWorld,
+	#[resource] map: &Grid<Tile>,
+	#[resource] player: &Player,
+	#[resource] lantern: &Lantern,
+	#[resource] light_map: &mut Grid<Light>,
+	#[resource] sky: &Sky,
+) {
+	light_map.iter_mut().for_each(|light| *light = Light::default());
+
+	let position = player.position;
+	// Only lights that could reach a tile the player can currently see matter.
+	let range = sky.sight_radius + MAX_LIGHT_RADIUS;
+	let in_range = |coord: Coord| position.distance2(coord) <= (range as u32).pow(2);
+
+	cast_light(position, lantern.0, map, light_map);
+
+	for (coord, light) in <(&Coord, &LightSource)>::query().iter(world) {
+		if in_range(*coord) {
+			cast_light(*coord, *light, map, light_map);
+		}
+	}
+
+	for x in position.x - range ..= position.x + range {
+		for y in position.y - range ..= position.y + range {
+			let coord = Coord { x, y };
+
+			if let Some(light) = map.get(coord).and_then(|tile| tile.tag.light()) {
+				if in_range(coord) {
+					cast_light(coord, light, map, light_map);
+				}
+			}
+		}
+	}
+}
@@ -1,17 +1,62 @@
 mod renderer;
+mod minimap;
+mod tileset;
+mod map;
+mod hot_reload;
+mod tween;
+mod light;
+mod weather;
+mod energy;
+mod level;
+mod chunks;
+mod items;
+mod save;
+mod prefabs;
+mod editor;
+mod validate;
+mod autotile;
+mod generate;
+mod regions;
+mod rng;
+mod replay;
+mod undo;
+mod debug_overlay;
 use legion::*;
+use legion::world::SubWorld;
 use renderer::*;
+use minimap::*;
+use tileset::{Tileset, Sprite};
+use tween::{Tween, TweenSettings, PlayerTween};
+use light::{Light, LightSource, Lantern};
+use weather::{WorldClock, Sky};
+use energy::{Speed, Elapsed, Noise, Health, ACTION_COST};
+use prefabs::Prefabs;
+use replay::Command;
+use tileset::TileProperties;
 use grid_2d::{Coord, Grid};
 
+const SIGHT_RADIUS: i32 = 10;
+
+// Where a new game starts, on the authored map.
+const START: Coord = Coord { x: 2, y: 2 };
+
 #[derive(Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize, PartialOrd, Ord)]
 struct TileLabel {
 	label: String,
 	#[serde(default)]
 	rotation: Rotation,
 	#[serde(default)]
+	flip: Flip,
+	#[serde(default)]
 	subsection: (u32, u32),
 }
 
+impl TileLabel {
+	fn new(label: &str) -> Self {
+		Self { label: label.to_string(), rotation: Rotation::Normal, flip: Flip::None, subsection: (0, 0) }
+	}
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize, Debug, PartialOrd, Ord)]
 enum Rotation {
 	Normal,
@@ -31,48 +76,40 @@ impl Rotation {
 	}
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for Rotation {
 	fn default() -> Self {
 		Self::Normal
 	}
 }
 
-pub enum Image {
-	Ground,
-	Rocks,
-	CaveEnterance,
-	Cave,
-	CaveWall,
-	Goop,
-	SnowyGround,
-	WaterCorner,
-	WaterEdge,
-	Water,
-	WaterInnerCorner,
-	Egg,
-	Person,
+// Mirroring is applied before the rotation.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, serde::Deserialize, serde::Serialize, Debug, PartialOrd, Ord, Default)]
+enum Flip {
+	#[default]
+	None,
+	// Swaps left and right.
+	Horizontal,
+	// Swaps top and bottom.
+	Vertical,
 }
 
-impl Image {
-	fn coords(&self) -> (u32, u32) {
+impl Flip {
+	// How the sprite's quad is scaled.
+	fn scale(self) -> [f32; 2] {
 		match self {
-			Self::Ground => (0, 0),
-			Self::Rocks => (1, 0),
-			Self::CaveEnterance => (2, 0),
-			Self::Cave => (3, 0),
-			Self::Goop => (0, 1),
-			Self::Egg => (1, 1),
-			Self::Person => (2, 1),
-			Self::CaveWall => (3, 1),
-			Self::SnowyGround => (0, 2),
-			Self::WaterCorner => (1, 2),
-			Self::WaterEdge => (2, 2),
-			Self::Water => (3, 2),
-			Self::WaterInnerCorner => (0, 3),
+			Flip::None => [1.0, 1.0],
+			Flip::Horizontal => [-1.0, 1.0],
+			Flip::Vertical => [1.0, -1.0],
 		}
 	}
 }
 
+// A sprite for an entity, looked up in the tileset's `sprites`.
+#[derive(Clone)]
+pub struct Image(pub String);
+
+#[derive(Copy, Clone)]
 enum TileTag {
 	Ground,
 	Rocks,
@@ -92,6 +129,18 @@ impl TileTag {
 		matches!(self, Self::CaveWall)
 	}
 
+	// Dark tiles can only be seen when they are lit.
+	fn is_dark(&self) -> bool {
+		matches!(self, Self::Cave | Self::CaveWall | Self::Goop)
+	}
+
+	fn light(&self) -> Option<LightSource> {
+		match self {
+			Self::Goop => Some(LightSource { radius: 2, colour: [0.2, 0.5, 0.2] }),
+			_ => None,
+		}
+	}
+
 	fn blocks_movement(&self) -> bool {
 		matches!(
 			self,
@@ -100,94 +149,259 @@ impl TileTag {
 		)
 	}
 
-	fn image(&self) -> Image {
+	fn from_label(label: &str) -> Option<Self> {
+		let tag = match label {
+			"ground" => Self::Ground,
+			"rocks" => Self::Rocks,
+			"cave_enterance" => Self::CaveEnterance,
+			"cave" => Self::Cave,
+			"cave_wall" => Self::CaveWall,
+			"goop" => Self::Goop,
+			"snowy_ground" => Self::SnowyGround,
+			"water_corner" => Self::WaterCorner,
+			"water_edge" => Self::WaterEdge,
+			"water" => Self::Water,
+			"water_inner_corner" => Self::WaterInnerCorner,
+			_ => return None,
+		};
+		Some(tag)
+	}
+
+	fn label(&self) -> &'static str {
 		match self {
-			Self::Ground => Image::Ground,
-			Self::Rocks => Image::Rocks,
-			Self::CaveEnterance => Image::CaveEnterance,
-			Self::Cave => Image::Cave,
-			Self::Goop => Image::Goop,
-			Self::CaveWall => Image::CaveWall,
-			Self::SnowyGround => Image::SnowyGround,
-			Self::WaterCorner => Image::WaterCorner,
-			Self::WaterEdge => Image::WaterEdge,
-			Self::Water => Image::Water,
-			Self::WaterInnerCorner => Image::WaterInnerCorner,
+			Self::Ground => "ground",
+			Self::Rocks => "rocks",
+			Self::CaveEnterance => "cave_enterance",
+			Self::Cave => "cave",
+			Self::CaveWall => "cave_wall",
+			Self::Goop => "goop",
+			Self::SnowyGround => "snowy_ground",
+			Self::WaterCorner => "water_corner",
+			Self::WaterEdge => "water_edge",
+			Self::Water => "water",
+			Self::WaterInnerCorner => "water_inner_corner",
 		}
 	}
 }
 
+#[derive(Copy, Clone)]
 struct BlocksMovement;
 
+#[derive(Clone)]
 struct Tile {
 	tag: TileTag,
 	rotation: Rotation,
+	flip: Flip,
+	sprite: Sprite,
+	properties: TileProperties,
 }
 
-fn load_world(world: &mut World, map: Grid<TileLabel>) -> Grid<Tile> {
-	Grid::new_grid_map_with_coord(map, |coord, label| {
-		Tile {
-			tag: match &label.label[..] {
-				"ground" => TileTag::Ground,
-				"rocks" => TileTag::Rocks,
-				"cave_enterance" => TileTag::CaveEnterance,
-				"cave" => TileTag::Cave,
-				"cave_wall" => TileTag::CaveWall,
-				"goop" => TileTag::Goop,
-				"egg" => {
-					world.push((coord, Image::Egg, BlocksMovement, Egg(255)));
-					TileTag::Goop
-				},
-				"snowy_ground" => TileTag::SnowyGround,
-				"water_corner" => TileTag::WaterCorner,
-				"water_edge" => TileTag::WaterEdge,
-				"water" => TileTag::Water,
-				"water_inner_corner" => TileTag::WaterInnerCorner,
-				other => panic!("{}", other)
-			},
-			rotation: label.rotation,
-		}
-	})
+fn load_world(world: &mut World, map: Grid<TileLabel>, tileset: &Tileset, prefabs: &Prefabs) -> Grid<Tile> {
+	Grid::new_grid_map_with_coord(map, |coord, label| load_tile(world, coord, &label, tileset, prefabs))
+}
+
+// Spawns any entities that the label places on the tile.
+fn load_tile(world: &mut World, coord: Coord, label: &TileLabel, tileset: &Tileset, prefabs: &Prefabs) -> Tile {
+	let definition = tileset.tiles.get(&label.label)
+		.unwrap_or_else(|| panic!("Tileset does not contain '{}'", label.label));
+
+	if let Some(prefab) = &definition.spawn {
+		prefabs.spawn(world, coord, prefab);
+	}
+
+	let terrain = definition.terrain.as_deref().unwrap_or(&label.label);
+	let tag = TileTag::from_label(terrain).unwrap_or_else(|| panic!("{}", terrain));
+
+	Tile {
+		sprite: tileset.tile_sprite(tag.label()),
+		properties: tileset.tile_properties(tag.label()),
+		tag,
+		rotation: label.rotation,
+		flip: label.flip,
+	}
 }
 
 pub struct Camera {
 	position: cgmath::Vector2<f32>,
 	zoom: f32,
+	window_size: cgmath::Vector2<f32>,
+}
+
+impl Camera {
+	// Finds the tile under a point in the window, given in pixels from the top left corner.
+	fn tile_at_pixel(&self, (x, y): (f64, f64)) -> Coord {
+		// Screen units are half pixels, with the origin in the middle and y pointing up.
+		let units = cgmath::vec2(x as f32 * 2.0 - self.window_size.x, self.window_size.y - y as f32 * 2.0);
+		let tile = cgmath::vec2(units.x, -units.y) / self.zoom + self.position;
+		Coord::new(tile.x.round() as i32, tile.y.round() as i32)
+	}
 }
 
+#[derive(Clone)]
 struct Player {
-	position: Coord
+	position: Coord,
+	speed: Speed,
+	health: Health,
+	inventory: items::Inventory,
 }
 
+#[derive(Copy, Clone)]
 struct Egg(u8);
 
 fn main() {
 	let mut world = legion::World::default();
 
-	let map_bytes = include_bytes!("wow.ron");
-	let map: Grid<TileLabel> = ron::de::from_bytes(&map_bytes[..]).unwrap();
-	let map = load_world(&mut world, map);
+	let assets = std::path::Path::new(tileset::ASSET_DIRECTORY);
+	let tileset_path = assets.join("alien.ron");
+	let map_path = assets.join("wow.ron");
+
+	// `snowy validate [map] [tileset]` checks a map against the tileset's rules, without starting the game.
+	let args: Vec<String> = std::env::args().collect();
+
+	if args.get(1).map(|arg| &arg[..]) == Some("validate") {
+		let map_path = args.get(2).map(std::path::PathBuf::from).unwrap_or(map_path);
+		let tileset_path = args.get(3).map(std::path::PathBuf::from).unwrap_or(tileset_path);
+
+		let tileset = Tileset::load(&tileset_path);
+		let map = map::load_map(&map_path).unwrap_or_else(|err| panic!("{}", err));
+		let violations = validate::validate(&map.terrain, &tileset);
+
+		for violation in &violations {
+			println!("{}", violation);
+		}
+
+		// Where the map can be walked is only worth reporting once its tiles are right.
+		if violations.is_empty() {
+			let regions = regions::Regions::of_labels(&map.terrain, &tileset);
+			let pockets = regions.pockets(START);
+			let reachable = regions.region(START).map_or(0, |region| regions.size(region));
+
+			println!(
+				"{} walkable regions, {} tiles reachable from the start, {} tiles in {} pockets",
+				regions.count(),
+				reachable,
+				pockets.iter().map(|pocket| pocket.len()).sum::<usize>(),
+				pockets.len(),
+			);
+		}
+
+		println!("{} violations in {}", violations.len(), map_path.display());
+		std::process::exit(if violations.is_empty() { 0 } else { 1 });
+	}
+
+	// `snowy generate <map> [seed] [width] [height] [--example <map>]` writes a new map that's playable
+	// from the start, following the tileset's rules or in the style of an example map.
+	if args.get(1).map(|arg| &arg[..]) == Some("generate") {
+		let mut args = args.clone();
+		let example = args.iter().position(|arg| arg == "--example").map(|index| {
+			let path = std::path::PathBuf::from(args.get(index + 1).expect("--example needs a map"));
+			args.drain(index ..= index + 1);
+			map::load_map(&path).unwrap_or_else(|err| panic!("{}", err))
+		});
+
+		let output = args.get(2).map(std::path::PathBuf::from).unwrap_or_else(|| assets.join("generated.ron"));
+		let number = |index: usize, default: u64| args.get(index).map_or(default, |arg| {
+			arg.parse().unwrap_or_else(|err| panic!("Failed to parse '{}': {}", arg, err))
+		});
+		let seed = number(3, 0);
+		let size = grid_2d::Size::new(number(4, 64) as u32, number(5, 64) as u32);
+
+		let tileset = Tileset::load(&tileset_path);
+		let constraints = generate::Constraints::playable(&tileset);
+
+		let generator = match &example {
+			Some(example) => generate::Generator::from_example(&tileset, &example.terrain, generate::PATTERN_SIZE),
+			None => generate::Generator::new(&tileset),
+		};
+
+		let terrain = generator.generate(size, &constraints, seed, 100)
+			.unwrap_or_else(|err| panic!("{}", err));
+		let map = map::MapFile { terrain, objects: Vec::new() };
+
+		let contents = ron::ser::to_string_pretty(&map, Default::default()).unwrap();
+		std::fs::write(&output, contents)
+			.unwrap_or_else(|err| panic!("Failed to write {}: {}", output.display(), err));
+
+		println!("Generated {}", output.display());
+		std::process::exit(0);
+	}
+
+	// `snowy replay [replay]` plays a recorded game back without a window, and checks that it ends up
+	// the same as when it was recorded.
+	if args.get(1).map(|arg| &arg[..]) == Some("replay") {
+		let path = args.get(2).map(std::path::PathBuf::from).unwrap_or_else(|| replay::REPLAY_PATH.into());
+		let recorded = replay::Replay::load(&path);
+
+		let hash = replay::play(&recorded, assets, std::path::Path::new(replay::CHUNK_DIRECTORY))
+			.unwrap_or_else(|err| {
+				println!("{}", err);
+				std::process::exit(1);
+			});
+		println!("Replayed {} commands from {}", recorded.commands.len(), path.display());
+
+		if hash != recorded.hash {
+			println!("The world's hash is {:016x}, but was {:016x} when it was recorded", hash, recorded.hash);
+			std::process::exit(1);
+		}
+
+		println!("The world's hash matches: {:016x}", hash);
+		std::process::exit(0);
+	}
+
+	let tileset = Tileset::load(&tileset_path);
+	let prefabs = Prefabs::load(&assets.join("prefabs.ron"));
+
+	// `--seed <seed>` starts a new game that plays out the same as any other run from that seed.
+	let seed = args.iter().position(|arg| arg == "--seed").map(|index| {
+		let arg = args.get(index + 1).expect("--seed needs a number");
+		arg.parse::<u64>().unwrap_or_else(|err| panic!("Failed to parse '{}': {}", arg, err))
+	});
+
+	// Carry on from the last game, unless asked not to.
+	let save = if seed.is_some() || args.iter().any(|arg| arg == "--new-game") {
+		None
+	} else {
+		save::load()
+	};
+
+	let rng = match &save {
+		Some(save) => save.rng.clone(),
+		None => rng::GameRng::new(seed.unwrap_or_else(rng::random_seed)),
+	};
+
+	// New games are recorded, so they can be played back.
+	let mut recording = if save.is_none() { Some(replay::Replay::new(rng.seed, &map_path)) } else { None };
+
+	// Debug mode watches the assets on disk and reloads them when they change, keeps the last few
+	// turns so they can be stepped back through, and has an overlay showing what blocks movement
+	// and sight.
+	let debug = args.iter().any(|arg| arg == "--debug");
+	let mut history = if debug { Some(undo::History::default()) } else { None };
+
+	let mut asset_watcher = if debug {
+		Some(hot_reload::AssetWatcher::new(tileset_path, map_path.clone(), &tileset))
+	} else {
+		None
+	};
 
 	use winit::event_loop::*;
 	use winit::event::*;
 
 	let event_loop = EventLoop::new();
-	let (mut renderer, buffer_renderer) = futures::executor::block_on(Renderer::new(&event_loop));
+	let (mut renderer, buffer_renderer) = futures::executor::block_on(Renderer::new(&event_loop, &tileset));
 
 	let mut resources = Resources::default();
 
-	let vis_map = Grid::new_grid_map_ref(&map, |_| Visibility::Invisible); 
-
 	resources.insert(buffer_renderer);
-	resources.insert(Camera {
-		position: cgmath::vec2(0.0, 0.0),
-		zoom: 64.0,
-	});
-	resources.insert(map);
-	resources.insert(vis_map);
-	resources.insert(Player {
-		position: Coord { x: 2, y: 2 },
-	});
+	resources.insert(editor::Editor::new(map_path.clone(), &tileset, &prefabs));
+	resources.insert(debug_overlay::DebugOverlay::default());
+	resources.insert(tileset);
+	resources.insert(prefabs);
+
+	start_game(&mut world, &mut resources, &map_path, save, rng, std::path::Path::new(chunks::SAVE_DIRECTORY));
+
+	let window_size = renderer.window.inner_size();
+	resources.get_mut::<Camera>().unwrap().window_size = cgmath::vec2(window_size.width as f32, window_size.height as f32);
 
 	#[derive(Default)]
 	struct KeyStates {
@@ -200,18 +414,30 @@ fn main() {
 	} 
 
 	let mut keys = KeyStates::default();
-
+	let mut modifiers = ModifiersState::default();
+	let mut title = String::new();
 
 	let mut rendering_schedule = Schedule::builder()
-		.add_system(render_map_system())
+		.add_system(tween::attach_tweens_system())
+		.flush()
 		.add_system(render_items_system())
 		.add_system(render_player_system())
+		.add_system(render_minimap_system())
+		.add_system(items::render_inventory_system())
+		.add_system(editor::render_editor_system())
+		.build();
+
+	let mut turn_schedule = add_turn_systems(&mut Schedule::builder())
+		.add_system(render_map_system())
 		.build();
 
-	let mut turn_schedule = Schedule::builder()
+	// Recalculates visibility without advancing a turn.
+	let mut vis_schedule = Schedule::builder()
 		.add_system(reset_vis_map_system())
-		.add_system(step_eggs_system())
+		.add_system(light::update_lighting_system())
 		.add_system(update_vis_system())
+		.add_system(update_minimap_system())
+		.add_system(render_map_system())
 		.build();
 
 	turn_schedule.execute(&mut world, &mut resources);
@@ -223,8 +449,25 @@ fn main() {
 			}
 			WindowEvent::Resized(size) => {
 				renderer.resize(size.width, size.height);
+				resources.get_mut::<Camera>().unwrap().window_size = cgmath::vec2(size.width as f32, size.height as f32);
 				*control_flow = ControlFlow::Poll;
 			}
+			WindowEvent::ModifiersChanged(state) => modifiers = state,
+			WindowEvent::CursorMoved { position, .. } if resources.get::<editor::Editor>().unwrap().enabled => {
+				let changed = editor::cursor_moved(&mut world, &mut resources, (position.x, position.y));
+
+				if changed {
+					vis_schedule.execute(&mut world, &mut resources);
+				}
+			}
+			WindowEvent::MouseInput { state, button, .. } if resources.get::<editor::Editor>().unwrap().enabled => {
+				let pressed = state == ElementState::Pressed;
+				let changed = editor::mouse_input(&mut world, &mut resources, button == MouseButton::Left, pressed);
+
+				if changed {
+					vis_schedule.execute(&mut world, &mut resources);
+				}
+			}
 			WindowEvent::KeyboardInput {
 				input:
 					KeyboardInput {
@@ -235,40 +478,119 @@ fn main() {
 				..
 			} => {
 				let pressed = state == ElementState::Pressed;
+				let editing = resources.get::<editor::Editor>().unwrap().enabled;
+
+				let mut command = None;
+				let mut map_changed = false;
+
+				let editor_key = if editing && pressed {
+					editor::key(&mut world, &mut resources, code, modifiers.ctrl())
+				} else {
+					None
+				};
+
+				if let Some(changed) = editor_key {
+					map_changed = changed;
+				} else {
+					match code {
+						VirtualKeyCode::X => keys.zoom_in = pressed,
+						VirtualKeyCode::Z => keys.zoom_out = pressed,
+						VirtualKeyCode::W => keys.up = pressed,
+						VirtualKeyCode::S => keys.down = pressed,
+						VirtualKeyCode::A => keys.left = pressed,
+						VirtualKeyCode::D => keys.right = pressed,
+						// The editor only works on the overworld, as caves are generated.
+						VirtualKeyCode::E if pressed && resources.get::<level::Levels>().unwrap().current == level::LevelId::Surface => {
+							let mut editor = resources.get_mut::<editor::Editor>().unwrap();
+							editor.enabled = !editor.enabled;
+							map_changed = true;
 
-				let mut move_dir = None;
-
-				match code {
-					VirtualKeyCode::X => keys.zoom_in = pressed,
-					VirtualKeyCode::Z => keys.zoom_out = pressed,
-					VirtualKeyCode::W => keys.up = pressed,
-					VirtualKeyCode::S => keys.down = pressed,
-					VirtualKeyCode::A => keys.left = pressed,
-					VirtualKeyCode::D => keys.right = pressed,
-					//VirtualKeyCode::Numpad1 if pressed => move_dir = Some(MovementDirection::DownLeft),
-					VirtualKeyCode::Numpad2 if pressed => move_dir = Some(MovementDirection::Down),
-					//VirtualKeyCode::Numpad3 if pressed => move_dir = Some(MovementDirection::DownRight),
-					VirtualKeyCode::Numpad4 if pressed => move_dir = Some(MovementDirection::Left),
-					VirtualKeyCode::Numpad5 if pressed => move_dir = Some(MovementDirection::StandStill),
-					VirtualKeyCode::Numpad6 if pressed => move_dir = Some(MovementDirection::Right),
-					//VirtualKeyCode::Numpad7 if pressed => move_dir = Some(MovementDirection::UpLeft),
-					VirtualKeyCode::Numpad8 if pressed => move_dir = Some(MovementDirection::Up),
-					//VirtualKeyCode::Numpad9 if pressed => move_dir = Some(MovementDirection::UpRight),
-					_ => {}
+							// Edits can't be played back, and undoing a turn would lose them.
+							if recording.take().is_some() {
+								eprintln!("Stopped recording a replay, as the map is being edited");
+							}
+							if let Some(history) = &mut history {
+								history.clear();
+							}
+						},
+						_ if editing => {},
+						VirtualKeyCode::T if pressed => {
+							let mut settings = resources.get_mut::<TweenSettings>().unwrap();
+							settings.enabled = !settings.enabled;
+						},
+						//VirtualKeyCode::Numpad1 if pressed => command = Some(Command::Move(MovementDirection::DownLeft)),
+						VirtualKeyCode::Numpad2 if pressed => command = Some(Command::Move(MovementDirection::Down)),
+						//VirtualKeyCode::Numpad3 if pressed => command = Some(Command::Move(MovementDirection::DownRight)),
+						VirtualKeyCode::Numpad4 if pressed => command = Some(Command::Move(MovementDirection::Left)),
+						VirtualKeyCode::Numpad5 if pressed => command = Some(Command::Move(MovementDirection::StandStill)),
+						VirtualKeyCode::Numpad6 if pressed => command = Some(Command::Move(MovementDirection::Right)),
+						//VirtualKeyCode::Numpad7 if pressed => command = Some(Command::Move(MovementDirection::UpLeft)),
+						VirtualKeyCode::Numpad8 if pressed => command = Some(Command::Move(MovementDirection::Up)),
+						//VirtualKeyCode::Numpad9 if pressed => command = Some(Command::Move(MovementDirection::UpRight)),
+						VirtualKeyCode::R if pressed => command = Some(Command::Rest),
+						VirtualKeyCode::I if pressed => {
+							let mut screen = resources.get_mut::<items::InventoryScreen>().unwrap();
+							screen.0 = !screen.0;
+						},
+						VirtualKeyCode::Key1 | VirtualKeyCode::Key2 | VirtualKeyCode::Key3 | VirtualKeyCode::Key4 |
+						VirtualKeyCode::Key5 | VirtualKeyCode::Key6 | VirtualKeyCode::Key7 | VirtualKeyCode::Key8 |
+						VirtualKeyCode::Key9 if pressed => {
+							command = Some(Command::Select(code as usize - VirtualKeyCode::Key1 as usize));
+						},
+						VirtualKeyCode::G if pressed => command = Some(Command::PickUp),
+						VirtualKeyCode::Q if pressed => command = Some(Command::Drop),
+						VirtualKeyCode::U if pressed => command = Some(Command::Use),
+						VirtualKeyCode::O if pressed && debug => {
+							resources.get_mut::<debug_overlay::DebugOverlay>().unwrap().cycle();
+							map_changed = true;
+						},
+						VirtualKeyCode::Back if pressed => if let Some(history) = &mut history {
+							if history.undo(&mut world, &mut resources) {
+								map_changed = true;
+
+								// The recording already has the turn that was undone in it.
+								if recording.take().is_some() {
+									eprintln!("Stopped recording a replay, as a turn was undone");
+								}
+							}
+						},
+						_ => {}
+					}
+				}
+
+				if map_changed {
+					vis_schedule.execute(&mut world, &mut resources);
 				}
 
-				if let Some(dir) = move_dir {
-					let moved = {
-						try_to_move_player(&mut world, &mut resources, dir)
-					};
-					if moved {
-						turn_schedule.execute(&mut world, &mut resources);
+				if let Some(command) = command {
+					if let Some(recording) = &mut recording {
+						recording.commands.push((resources.get::<WorldClock>().unwrap().ticks, command));
+					}
+
+					let snapshot = history.as_ref().map(|_| undo::Snapshot::take(&world, &resources));
+					perform(&mut world, &mut resources, &mut turn_schedule, command);
+
+					if let (Some(history), Some(snapshot)) = (&mut history, snapshot) {
+						history.push(snapshot, &resources);
 					}
 				}
 			}
 			_ => {}
 		},
 		Event::MainEventsCleared => {
+			if let Some(watcher) = &mut asset_watcher {
+				if watcher.poll(&mut world, &mut resources, &mut renderer) {
+					vis_schedule.execute(&mut world, &mut resources);
+
+					if recording.take().is_some() {
+						eprintln!("Stopped recording a replay, as the assets were reloaded");
+					}
+					if let Some(history) = &mut history {
+						history.clear();
+					}
+				}
+			}
+
 			{
 				let mut camera = resources.get_mut::<Camera>().unwrap();
 
@@ -294,55 +616,125 @@ fn main() {
 				}
 			}
 
+			{
+				let editor = resources.get::<editor::Editor>().unwrap();
+				let seed = resources.get::<rng::GameRng>().unwrap().seed;
+				let new_title = if editor.enabled { editor.status() } else { format!("Snowy (seed {})", seed) };
+
+				if new_title != title {
+					renderer.window.set_title(&new_title);
+					title = new_title;
+				}
+			}
+
 			rendering_schedule.execute(&mut world, &mut resources);
 			renderer.request_redraw();
 		},
-		Event::RedrawRequested(_) => renderer.render(&mut resources.get_mut().unwrap()),
-		Event::LoopDestroyed => {}//world.fetch::<ControlsState>().save(),
+		Event::RedrawRequested(_) => renderer.render(
+			&mut resources.get_mut().unwrap(), &mut resources.get_mut().unwrap(), &resources.get().unwrap(),
+		),
+		Event::LoopDestroyed => {
+			// Saving takes the player back up to the surface, so the hash is taken first.
+			if let Some(recording) = &mut recording {
+				recording.hash = replay::world_hash(&world, &resources);
+				recording.save(std::path::Path::new(replay::REPLAY_PATH));
+			}
+
+			save::save(&mut world, &mut resources);
+		},
 		_ => {}
 	});
 }
 
+// Run at the end of each turn, as the terrain doesn't change in between.
 #[legion::system]
+#[read_component(Coord)]
+#[read_component(BlocksMovement)]
+#[allow(clippy::too_many_arguments)]
 fn render_map(
+	world: &mut SubWorld,
 	#[resource] map: &Grid<Tile>,
-	#[resource] camera: &Camera,
 	#[resource] buffers: &mut BufferRenderer,
 	#[resource] vis_map: &Grid<Visibility>,
+	#[resource] light_map: &Grid<Light>,
+	#[resource] sky: &Sky,
+	#[resource] editor: &editor::Editor,
+	#[resource] debug_overlay: &debug_overlay::DebugOverlay,
+	#[resource] player: &Player,
 ) {
+	buffers.clear_terrain();
+
+	let debug_colours = debug_overlay.colours(world, map, light_map, player, sky);
+
 	map.enumerate()
 		.zip(vis_map.iter())
-		.filter(|(_, vis)| vis != &&Visibility::Invisible)
-		.for_each(|((Coord { x, y }, tile), vis)| {
-			let overlay = vis.overlay();
+		.zip(light_map.iter())
+		.filter(|((_, vis), _)| editor.enabled || debug_colours.is_some() || vis != &&Visibility::Invisible)
+		.for_each(|(((Coord { x, y }, tile), vis), light)| {
+			// Everything is shown fully lit while editing, or under the debug overlay.
+			let (overlay, tint) = if editor.enabled {
+				([0.0; 4], [1.0; 3])
+			} else if let Some(colours) = &debug_colours {
+				(*colours.get_checked(Coord { x, y }), [1.0; 3])
+			} else {
+				(vis.overlay(sky), vis.tint(light, tile, sky))
+			};
 
-			buffers.render(cgmath::vec2(x as f32, y as f32), tile.rotation.to_deg(), &tile.tag.image(), camera, overlay);
+			buffers.render_terrain(cgmath::vec2(x as f32, y as f32), tile.rotation.to_deg(), tile.flip, tile.sprite, overlay, tint);
 		});
 }
 
-
 #[legion::system(for_each)]
+#[allow(clippy::too_many_arguments)]
 fn render_items(
-	position: &Coord, image: &Image,
+	position: &Coord, image: &Image, egg: Option<&Egg>, rotation: Option<&Rotation>, tween: Option<&mut Tween>,
 	#[resource] buffers: &mut BufferRenderer,
-	#[resource] camera: &Camera,
+	#[resource] tileset: &Tileset,
 	#[resource] vis_map: &Grid<Visibility>,
-
+	#[resource] light_map: &Grid<Light>,
+	#[resource] map: &Grid<Tile>,
+	#[resource] sky: &Sky,
+	#[resource] tween_settings: &TweenSettings,
+	#[resource] editor: &editor::Editor,
 ) {
-	let vis = vis_map.get_checked(*position);
+	let vis = if editor.enabled { &Visibility::Visible } else { vis_map.get_checked(*position) };
+	let tint = if editor.enabled {
+		[1.0; 3]
+	} else {
+		vis.tint(light_map.get_checked(*position), map.get_checked(*position), sky)
+	};
+
+	// Eggs pulse faster as they get closer to hatching.
+	let animation_speed = egg.map(|egg| 1.0 + 3.0 * (1.0 - egg.0 as f32 / 255.0)).unwrap_or(1.0);
+
+	let rotation = rotation.map(|rotation| rotation.to_deg()).unwrap_or(0.0);
+
+	let (tile_position, rotation) = match tween {
+		Some(tween) => tween.update(*position, rotation, tween_settings),
+		None => (cgmath::vec2(position.x as f32, position.y as f32), rotation),
+	};
 
 	if vis == &Visibility::Visible {
-		buffers.render(cgmath::vec2(position.x as f32, position.y as f32), 0.0, image, camera, vis.overlay());
+		buffers.render(tile_position, rotation, Flip::None, tileset.entity_sprite(&image.0), vis.overlay(sky), tint, animation_speed);
 	}
 }
 
 #[legion::system]
+#[allow(clippy::too_many_arguments)]
 fn render_player(
 	#[resource] buffers: &mut BufferRenderer,
 	#[resource] player: &Player,
-	#[resource] camera: &Camera,
+	#[resource] tileset: &Tileset,
+	#[resource] tween: &mut PlayerTween,
+	#[resource] tween_settings: &TweenSettings,
+	#[resource] light_map: &Grid<Light>,
+	#[resource] map: &Grid<Tile>,
+	#[resource] sky: &Sky,
 ) {
-	buffers.render(cgmath::vec2(player.position.x as f32, player.position.y as f32), 0.0, &Image::Person, camera, [0.0; 4]);
+	let (position, rotation) = tween.0.update(player.position, 0.0, tween_settings);
+	let tint = light_map.get_checked(player.position).tint(map.get_checked(player.position), sky.ambient);
+
+	buffers.render(position, rotation, Flip::None, tileset.entity_sprite("person"), sky.overlay, tint, 1.0);
 }
 
 #[legion::system]
@@ -354,37 +746,27 @@ fn reset_vis_map(
 	});
 }
 
-#[legion::system(for_each)]
-fn step_eggs(
-	entity: &Entity,
-	egg: &mut Egg,
-	buffer: &mut legion::systems::CommandBuffer,
-) {
-	egg.0 -= 1;
-	if egg.0 == 0 {
-		buffer.remove(*entity);
-	}
-}
-
 #[legion::system]
 fn update_vis(
 	#[resource] player: &Player,
 	#[resource] map: &Grid<Tile>,
 	#[resource] vis_map: &mut Grid<Visibility>,
+	#[resource] light_map: &Grid<Light>,
+	#[resource] sky: &Sky,
 ) {
 	let position = player.position;
 
-	let radius = 10;
+	let radius = sky.sight_radius;
 
 	for x in position.x - radius ..= position.x + radius {
 		for y in position.y - radius ..= position.y + radius {
 			if let Some(vis) = vis_map.get_mut(Coord { x, y }) {
 				if position.distance2(Coord { x, y }) <= (radius as u32).pow(2) {
-					let is_visible = line_drawing::Bresenham::new((x, y), (position.x, position.y))
-						.skip(1)
-						.all(|(x, y)| !map.get_checked(Coord { x, y }).tag.blocks_sight());
+					let is_visible = in_line_of_sight(map, Coord { x, y }, position);
+
+					let is_lit = light_map.get_checked(Coord { x, y }).can_see(map.get_checked(Coord { x, y }));
 
-					if is_visible {
+					if is_visible && is_lit {
 						*vis = Visibility::Visible;
 					}
 				}
@@ -393,7 +775,15 @@ fn update_vis(
 	}
 }
 
-#[derive(PartialEq)]
+// Whether nothing on the way from `from` to `to` blocks sight. `from` itself isn't checked, so
+// walls can be seen.
+fn in_line_of_sight(map: &Grid<Tile>, from: Coord, to: Coord) -> bool {
+	line_drawing::Bresenham::new((from.x, from.y), (to.x, to.y))
+		.skip(1)
+		.all(|(x, y)| !map.get_checked(Coord { x, y }).tag.blocks_sight())
+}
+
+#[derive(Copy, Clone, PartialEq)]
 enum Visibility {
 	Invisible,
 	Visible,
@@ -401,16 +791,24 @@ enum Visibility {
 }
 
 impl Visibility {
-	fn overlay(&self) -> [f32; 4] {
+	fn overlay(&self, sky: &Sky) -> [f32; 4] {
 		match self {
 			Visibility::Invisible => [0.0, 0.0, 0.0, 1.0],
 			Visibility::PreviouslyVisible => [0.0, 0.0, 0.0, 0.75],
-			Visibility::Visible => [0.0; 4],
+			Visibility::Visible => sky.overlay,
+		}
+	}
+
+	// Only the light on visible tiles is shown, remembered tiles are drawn as they were lit.
+	fn tint(&self, light: &Light, tile: &Tile, sky: &Sky) -> [f32; 3] {
+		match self {
+			Visibility::Visible => light.tint(tile, sky.ambient),
+			_ => [1.0; 3],
 		}
 	}
 }
 
-#[derive(PartialEq)]
+#[derive(Copy, Clone, PartialEq, Debug, serde::Serialize, serde::Deserialize)]
 enum MovementDirection {
 	Up,
 	Down,
@@ -439,27 +837,213 @@ impl MovementDirection {
 	}
 }
 
-fn try_to_move_player(world: &mut World, resources: &mut Resources, direction: MovementDirection) -> bool {
+// Sets up everything that taking turns needs, for a game that's carried on from `save` or started
+// afresh from `rng`'s seed. The tileset and prefabs have to be in `resources` already.
+fn start_game(
+	world: &mut World, resources: &mut Resources, map_path: &std::path::Path,
+	save: Option<save::SaveGame>, rng: rng::GameRng, chunk_saves: &std::path::Path,
+) {
+	if save.is_none() {
+		chunks::clear_saves(chunk_saves);
+	}
+
+	// The player's starting position, relative to the authored map.
+	let start = save.as_ref().map(|save| save.position).unwrap_or(START);
+
+	let map = map::load_map(map_path).unwrap_or_else(|err| panic!("{}", err));
+	let overworld = chunks::Overworld::new(map, start, rng.seed, chunk_saves);
+	let (map, vis_map) = overworld.build_window(world, &resources.get().unwrap(), &resources.get().unwrap());
+	let start = overworld.to_local(start);
+
+	// A new game starts on the nearest tile in the largest area that can be walked around, and
+	// not on top of anything that's in the way.
+	let start = if save.is_some() {
+		start
+	} else {
+		let occupied: std::collections::HashSet<Coord> = <(&Coord, &BlocksMovement)>::query().iter(world)
+			.map(|(position, _)| *position)
+			.collect();
+
+		regions::Regions::new(map.size(), |position| {
+			!map.get_checked(position).tag.blocks_movement() && !occupied.contains(&position)
+		}).spawn_point(start).unwrap_or(start)
+	};
+
+	// The window's size is filled in once there is one.
+	resources.insert(Camera {
+		position: cgmath::vec2(start.x as f32, start.y as f32),
+		zoom: 64.0,
+		window_size: cgmath::vec2(0.0, 0.0),
+	});
+	resources.insert(Minimap::new(map.size()));
+	resources.insert(Grid::new_grid_map_ref(&map, |_| Light::default()));
+	resources.insert(map);
+	resources.insert(vis_map);
+	let clock = WorldClock { ticks: save.as_ref().map(|save| save.ticks).unwrap_or(0) };
+	resources.insert(Sky::new(&clock, rng.seed));
+	resources.insert(clock);
+	resources.insert(rng);
+	resources.insert(Lantern(LightSource { radius: 5, colour: [1.0, 0.85, 0.6] }));
+	resources.insert(PlayerTween(Tween::new(start, 0.0)));
+	resources.insert(TweenSettings::default());
+	let (health, inventory) = match save {
+		Some(save) => {
+			let mut inventory = save.inventory;
+			inventory.forget_unknown(&resources.get().unwrap(), &resources.get().unwrap());
+			(save.health, inventory)
+		},
+		None => (Health::new(20), items::Inventory::new(8)),
+	};
+
+	resources.insert(Player {
+		position: start,
+		speed: Speed(100),
+		health,
+		inventory,
+	});
+	resources.insert(items::InventoryScreen::default());
+	resources.insert(Elapsed(0));
+	resources.insert(level::Levels::default());
+	resources.insert(overworld);
+	resources.insert(Noise { origin: start, radius: 0 });
+}
+
+// Everything that happens in a turn apart from drawing it, which replays leave out.
+fn add_turn_systems(builder: &mut legion::systems::Builder) -> &mut legion::systems::Builder {
+	builder
+		.add_system(weather::advance_clock_system())
+		.add_system(reset_vis_map_system())
+		.add_system(energy::regenerate_system())
+		.add_system(weather::exposure_system())
+		.add_system(energy::accrue_energy_system())
+		.add_system(energy::disturb_eggs_system())
+		.add_system(energy::step_eggs_system())
+		.add_system(items::burn_out_system())
+		.add_system(weather::snowfall_system())
+		.add_system(light::update_lighting_system())
+		.add_system(update_vis_system())
+		.add_system(update_minimap_system())
+}
+
+// Carries out one of the player's commands, taking a turn if it took any time.
+fn perform(world: &mut World, resources: &mut Resources, turn_schedule: &mut Schedule, command: Command) {
+	let action: Option<fn(&mut World, &mut Resources) -> Option<u32>> = match command {
+		Command::Move(direction) => {
+			let start = resources.get::<Player>().unwrap().position;
+
+			if let Some(cost) = try_to_move_player(world, resources, direction) {
+				if resources.get::<Player>().unwrap().position != start {
+					level::use_entrance(world, resources);
+					chunks::recentre(world, resources);
+				}
+				take_turn(world, resources, turn_schedule, cost);
+			}
+
+			None
+		},
+		Command::Rest => {
+			rest(world, resources, turn_schedule);
+			None
+		},
+		Command::Select(slot) => {
+			resources.get_mut::<Player>().unwrap().inventory.select(slot);
+			None
+		},
+		Command::PickUp => Some(items::pick_up),
+		Command::Drop => Some(items::drop_item),
+		Command::Use => Some(items::use_item),
+	};
+
+	if let Some(cost) = action.and_then(|action| action(world, resources)) {
+		take_turn(world, resources, turn_schedule, cost);
+	}
+}
+
+// Passes the time that the player's action took, letting everything else act in the meantime.
+fn take_turn(world: &mut World, resources: &mut Resources, turn_schedule: &mut Schedule, cost: u32) {
+	let ticks = energy::action_ticks(cost, &resources.get::<Player>().unwrap().speed);
+	resources.insert(Elapsed(ticks));
+	turn_schedule.execute(world, resources);
+
+	// Only the action that made a noise disturbs anything, so resting or waiting afterwards is quiet.
+	resources.get_mut::<Noise>().unwrap().radius = 0;
+}
+
+// Waits until the player is fully healed, stopping early if they get hurt or something new comes into view.
+fn rest(world: &mut World, resources: &mut Resources, turn_schedule: &mut Schedule) {
+	const MAX_TURNS: u32 = 500;
+
+	let visible_eggs = |world: &World, resources: &Resources| {
+		let vis_map = resources.get::<Grid<Visibility>>().unwrap();
+		<(&Coord, &Egg)>::query().iter(world)
+			.filter(|(coord, _)| *vis_map.get_checked(**coord) == Visibility::Visible)
+			.count()
+	};
+
+	let eggs_in_view = visible_eggs(world, resources);
+
+	for _ in 0 .. MAX_TURNS {
+		let health = resources.get::<Player>().unwrap().health.current;
+
+		if resources.get::<Player>().unwrap().health.is_full() {
+			break;
+		}
+
+		take_turn(world, resources, turn_schedule, ACTION_COST);
+
+		if resources.get::<Player>().unwrap().health.current < health || visible_eggs(world, resources) > eggs_in_view {
+			break;
+		}
+	}
+}
+
+// Returns the energy cost of the move, or `None` if the player couldn't move at all.
+fn try_to_move_player(world: &mut World, resources: &mut Resources, direction: MovementDirection) -> Option<u32> {
 	if direction == MovementDirection::StandStill {
-		return true;
+		return Some(ACTION_COST);
 	}
 
 	let mut player = resources.get_mut::<Player>().unwrap();
+	let mut noise = resources.get_mut::<Noise>().unwrap();
 	let grid = resources.get::<Grid<Tile>>().unwrap();
 
+	let can_move_to = |coord: Coord| {
+		let entity_at = <(&Coord, &BlocksMovement)>::query().iter(world).any(|(position, _)| *position == coord);
+		grid.get(coord).map(|tile| (!tile.tag.blocks_movement(), !entity_at))
+	};
+
 	let new_coord = player.position + direction.relative_coord();
 
+	match can_move_to(new_coord) {
+		None | Some((false, _)) => None,
+		// Bumping into something still takes a turn.
+		Some((true, false)) => Some(ACTION_COST),
+		Some((true, true)) => {
+			let leaving = grid.get_checked(player.position).properties;
+			let mut cost = leaving.stickiness;
+			let mut position = new_coord;
+
+			cost += grid.get_checked(position).properties.movement_cost;
 
-	match grid.get(new_coord) {
-		None => false,
-		Some(tile) => {
-			let can_move = !tile.tag.blocks_movement();
-			let entity_at = <(&Coord, &BlocksMovement)>::query().iter(world).any(|(coord, _)| *coord == new_coord);
+			// Slide across slippery tiles for free, until hitting something.
+			let mut slid = 0;
+			while grid.get_checked(position).properties.slippery && slid < 16 {
+				let next = position + direction.relative_coord();
 
-			if can_move && !entity_at {
-				player.position = new_coord;
+				match can_move_to(next) {
+					Some((true, true)) => position = next,
+					_ => break,
+				}
+				slid += 1;
 			}
-			can_move
+
+			player.position = position;
+			*noise = Noise {
+				origin: position,
+				radius: grid.get_checked(position).properties.noise,
+			};
+
+			Some(cost)
 		}
 	}
 }
@@ -0,0 +1,48 @@
+use std::path::Path;
+use grid_2d::{Coord, Grid};
+use crate::{TileLabel, Rotation};
+
+// A map is its terrain, plus the entities placed on top of it.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct MapFile {
+	pub terrain: Grid<TileLabel>,
+	#[serde(default)]
+	pub objects: Vec<ObjectSpawn>,
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct ObjectSpawn {
+	pub position: Coord,
+	pub prefab: String,
+	#[serde(default)]
+	pub overrides: Overrides,
+}
+
+// Changes to the components that the prefab starts with.
+#[derive(Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Overrides {
+	#[serde(default)]
+	pub hatch_timer: Option<u8>,
+	#[serde(default)]
+	pub rotation: Option<Rotation>,
+	// Ticks left on a light, for ones that have been burning.
+	#[serde(default)]
+	pub fuel: Option<u32>,
+	// Energy built up towards the next action.
+	#[serde(default)]
+	pub energy: Option<u32>,
+}
+
+// Older maps are just the terrain, with entities placed by labels such as "egg".
+pub fn load_map(path: &Path) -> Result<MapFile, String> {
+	let bytes = std::fs::read(path)
+		.map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+
+	// It isn't known which format a broken map was meant to be in, so both errors are reported.
+	ron::de::from_bytes(&bytes).or_else(|map_err| match ron::de::from_bytes::<Grid<TileLabel>>(&bytes) {
+		Ok(terrain) => Ok(MapFile { terrain, objects: Vec::new() }),
+		Err(terrain_err) => Err(format!(
+			"Failed to parse {} as a map ({}) or as just its terrain ({})", path.display(), map_err, terrain_err,
+		)),
+	})
+}
@@ -0,0 +1,15 @@
+#version 450
+
+layout(location = 0) out vec4 outColor;
+layout(location = 0) in vec2 out_uv;
+layout(location = 1) in vec4 out_colour;
+layout(location = 2) in float out_textured;
+
+layout(set = 0, binding = 0) uniform texture2D tex;
+layout(set = 0, binding = 1) uniform sampler samp;
+
+void main() {
+    vec4 texture_colour = texture(sampler2D(tex, samp), out_uv);
+
+    outColor = mix(out_colour, texture_colour * out_colour, out_textured);
+}
@@ -0,0 +1,112 @@
+use grid_2d::{Coord, Grid, Size};
+use crate::{Tile, TileTag, Visibility, Camera, Player};
+use crate::renderer::BufferRenderer;
+
+// Gap between the minimap and the edge of the window, in pixels.
+const MARGIN: f32 = 10.0;
+
+pub struct Minimap {
+	pub size: Size,
+	pub pixels: Vec<u8>,
+	pub dirty: bool,
+}
+
+impl Minimap {
+	pub fn new(size: Size) -> Self {
+		Self {
+			size,
+			pixels: vec![0; size.count() * 4],
+			dirty: true,
+		}
+	}
+}
+
+fn tile_colour(tag: &TileTag) -> [u8; 3] {
+	match tag {
+		TileTag::Ground => [120, 110, 100],
+		TileTag::Rocks => [80, 80, 80],
+		TileTag::CaveEnterance => [200, 150, 60],
+		TileTag::Cave => [70, 55, 50],
+		TileTag::CaveWall => [40, 30, 30],
+		TileTag::Goop => [90, 160, 60],
+		TileTag::SnowyGround => [230, 235, 245],
+		TileTag::WaterCorner | TileTag::WaterEdge | TileTag::WaterInnerCorner => [70, 120, 200],
+		TileTag::Water => [40, 80, 170],
+	}
+}
+
+// Only marks the minimap dirty if a pixel actually changed, so the texture is only re-uploaded
+// when `update_vis` changes the explored set.
+#[legion::system]
+pub fn update_minimap(
+	#[resource] map: &Grid<Tile>,
+	#[resource] vis_map: &Grid<Visibility>,
+	#[resource] minimap: &mut Minimap,
+) {
+	for ((tile, vis), pixel) in map.iter().zip(vis_map.iter()).zip(minimap.pixels.chunks_mut(4)) {
+		let [r, g, b] = tile_colour(&tile.tag);
+
+		let colour = match vis {
+			Visibility::Invisible => [0, 0, 0, 0],
+			Visibility::PreviouslyVisible => [r / 2, g / 2, b / 2, 255],
+			Visibility::Visible => [r, g, b, 255],
+		};
+
+		if *pixel != colour[..] {
+			pixel.copy_from_slice(&colour);
+			minimap.dirty = true;
+		}
+	}
+}
+
+#[legion::system]
+pub fn render_minimap(
+	#[resource] minimap: &Minimap,
+	#[resource] player: &Player,
+	#[resource] camera: &Camera,
+	#[resource] buffers: &mut BufferRenderer,
+) {
+	// The shaders work in units of half a pixel, so one pixel per tile means two units per tile.
+	let dimensions = cgmath::vec2(minimap.size.width() as f32, minimap.size.height() as f32);
+	let center = camera.window_size - dimensions - cgmath::vec2(MARGIN, MARGIN) * 2.0;
+	let top_left = center + cgmath::vec2(-dimensions.x, dimensions.y);
+
+	let tile_to_screen = |x: f32, y: f32| top_left + cgmath::vec2(x, -y) * 2.0;
+
+	buffers.render_minimap_quad(center, dimensions, [1.0; 4], true);
+
+	let Coord { x, y } = player.position;
+	buffers.render_minimap_quad(tile_to_screen(x as f32 + 0.5, y as f32 + 0.5), cgmath::vec2(2.0, 2.0), [1.0, 0.2, 0.2, 1.0], false);
+
+	// The player's health, as a bar underneath the minimap.
+	let bar_center = center - cgmath::vec2(0.0, dimensions.y + MARGIN * 2.0);
+	let health = player.health.fraction();
+	buffers.render_minimap_quad(bar_center, cgmath::vec2(dimensions.x, 4.0), [0.2, 0.05, 0.05, 1.0], false);
+	buffers.render_minimap_quad(
+		bar_center - cgmath::vec2(dimensions.x * (1.0 - health), 0.0),
+		cgmath::vec2(dimensions.x * health, 4.0), [0.8, 0.1, 0.1, 1.0], false,
+	);
+
+	// Outline the part of the map that the camera can currently see. Tiles are centered on their
+	// coordinates, so the camera position is offset by half a tile.
+	let half_extent = camera.window_size / camera.zoom;
+	let min_x = (camera.position.x + 0.5 - half_extent.x).max(0.0);
+	let min_y = (camera.position.y + 0.5 - half_extent.y).max(0.0);
+	let max_x = (camera.position.x + 0.5 + half_extent.x).min(dimensions.x);
+	let max_y = (camera.position.y + 0.5 + half_extent.y).min(dimensions.y);
+
+	if min_x >= max_x || min_y >= max_y {
+		return;
+	}
+
+	let colour = [1.0, 1.0, 0.3, 1.0];
+	let mid_x = (min_x + max_x) / 2.0;
+	let mid_y = (min_y + max_y) / 2.0;
+	let width = max_x - min_x;
+	let height = max_y - min_y;
+
+	buffers.render_minimap_quad(tile_to_screen(mid_x, min_y), cgmath::vec2(width, 1.0), colour, false);
+	buffers.render_minimap_quad(tile_to_screen(mid_x, max_y), cgmath::vec2(width, 1.0), colour, false);
+	buffers.render_minimap_quad(tile_to_screen(min_x, mid_y), cgmath::vec2(1.0, height), colour, false);
+	buffers.render_minimap_quad(tile_to_screen(max_x, mid_y), cgmath::vec2(1.0, height), colour, false);
+}
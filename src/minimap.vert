@@ -0,0 +1,24 @@
+#version 450
+
+layout(location = 0) in vec2 v_point;
+
+layout(location = 1) in vec2 i_center;
+layout(location = 2) in vec2 i_dimensions;
+layout(location = 3) in vec4 i_colour;
+layout(location = 4) in float i_textured;
+
+layout(location = 0) out vec2 out_uv;
+layout(location = 1) out vec4 out_colour;
+layout(location = 2) out float out_textured;
+
+layout(set = 0, binding = 2) uniform Uniforms {
+    vec2 window_size;
+};
+
+void main() {
+    gl_Position = vec4((i_center + i_dimensions * v_point) / window_size, 0.0, 1.0);
+
+    out_uv = vec2(0.5, 0.5) + vec2(1.0, -1.0) * v_point * 0.5;
+    out_colour = i_colour;
+    out_textured = i_textured;
+}
@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::path::Path;
+use legion::*;
+use grid_2d::Coord;
+use crate::{Image, Egg, BlocksMovement};
+use crate::map::ObjectSpawn;
+use crate::light::LightSource;
+use crate::energy::{Speed, Energy};
+use crate::items::{Item, ItemKind, Fuel};
+
+// A component that a prefab's entities start with.
+#[derive(serde::Deserialize)]
+pub enum ComponentDef {
+	// A sprite label, looked up in the tileset's `sprites`.
+	Image(String),
+	BlocksMovement,
+	// Turns until it hatches.
+	Egg(u8),
+	Light { radius: i32, colour: [f32; 3] },
+	// Also gives the entity energy, so that it takes turns.
+	Speed(u32),
+	Item(ItemKind),
+	// Ticks until it burns out.
+	Fuel(u32),
+}
+
+#[derive(serde::Deserialize)]
+pub struct SpawnChance {
+	pub prefab: String,
+	// The chance of spawning on each open tile.
+	pub chance: f32,
+}
+
+#[derive(serde::Deserialize)]
+pub struct Prefabs {
+	prefabs: HashMap<String, Vec<ComponentDef>>,
+	#[serde(default)]
+	spawn_tables: HashMap<String, Vec<SpawnChance>>,
+}
+
+// The prefab an entity was spawned from, so that it can be saved and spawned again later.
+#[derive(Clone)]
+pub struct Prefab(pub String);
+
+impl Prefabs {
+	pub fn load(path: &Path) -> Self {
+		let bytes = std::fs::read(path)
+			.unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+		ron::de::from_bytes(&bytes)
+			.unwrap_or_else(|err| panic!("Failed to parse {}: {}", path.display(), err))
+	}
+
+	fn components(&self, name: &str) -> &[ComponentDef] {
+		self.prefabs.get(name)
+			.unwrap_or_else(|| panic!("There is no prefab named '{}'", name))
+	}
+
+	pub fn spawn(&self, world: &mut World, coord: Coord, name: &str) -> Entity {
+		let entity = world.push((coord, Prefab(name.to_string())));
+		let mut entry = world.entry(entity).unwrap();
+
+		for component in self.components(name) {
+			match component {
+				ComponentDef::Image(label) => entry.add_component(Image(label.clone())),
+				ComponentDef::BlocksMovement => entry.add_component(BlocksMovement),
+				ComponentDef::Egg(timer) => entry.add_component(Egg(*timer)),
+				ComponentDef::Light { radius, colour } => {
+					entry.add_component(LightSource { radius: *radius, colour: *colour });
+				},
+				ComponentDef::Speed(speed) => {
+					entry.add_component(Speed(*speed));
+					entry.add_component(Energy(0));
+				},
+				ComponentDef::Item(_) => entry.add_component(Item),
+				ComponentDef::Fuel(fuel) => entry.add_component(Fuel(*fuel)),
+			}
+		}
+
+		entity
+	}
+
+	// Spawns an object from a map or chunk file, relative to `offset`.
+	pub fn spawn_object(&self, world: &mut World, offset: Coord, object: &ObjectSpawn) -> Entity {
+		let entity = self.spawn(world, offset + object.position, &object.prefab);
+		let mut entry = world.entry(entity).unwrap();
+
+		if let Some(timer) = object.overrides.hatch_timer {
+			if let Ok(egg) = entry.get_component_mut::<Egg>() {
+				egg.0 = timer;
+			}
+		}
+
+		if let Some(fuel) = object.overrides.fuel {
+			if let Ok(Fuel(left)) = entry.get_component_mut::<Fuel>() {
+				*left = fuel;
+			}
+		}
+
+		if let Some(energy) = object.overrides.energy {
+			if let Ok(Energy(built_up)) = entry.get_component_mut::<Energy>() {
+				*built_up = energy;
+			}
+		}
+
+		if let Some(rotation) = object.overrides.rotation {
+			entry.add_component(rotation);
+		}
+
+		entity
+	}
+
+	pub fn contains(&self, name: &str) -> bool {
+		self.prefabs.contains_key(name)
+	}
+
+	pub fn names(&self) -> Vec<String> {
+		let mut names: Vec<String> = self.prefabs.keys().cloned().collect();
+		names.sort();
+		names
+	}
+
+	// The sprite label that the prefab's entities are drawn with.
+	pub fn image(&self, name: &str) -> Option<&str> {
+		self.components(name).iter().find_map(|component| match component {
+			ComponentDef::Image(label) => Some(&label[..]),
+			_ => None,
+		})
+	}
+
+	// What a prefab does when it's used from the inventory, if it exists and is an item at all.
+	pub fn item_kind(&self, name: &str) -> Option<ItemKind> {
+		self.prefabs.get(name)?.iter().find_map(|component| match component {
+			ComponentDef::Item(kind) => Some(*kind),
+			_ => None,
+		})
+	}
+
+	pub fn spawn_table(&self, name: &str) -> &[SpawnChance] {
+		self.spawn_tables.get(name).map(|table| &table[..]).unwrap_or(&[])
+	}
+}
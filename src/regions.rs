@@ -0,0 +1,195 @@
+use std::collections::VecDeque;
+use grid_2d::{Coord, Grid, Size};
+use crate::{TileLabel, TileTag};
+use crate::tileset::{Tileset, Direction};
+
+// The areas of a map that can be walked between, numbered from 0.
+pub struct Regions {
+	ids: Grid<Option<usize>>,
+	sizes: Vec<usize>,
+}
+
+// What to do with the areas that can't be reached: pockets with fewer tiles than `fill_below`
+// are filled in, and the rest are joined up with a corridor.
+#[derive(Copy, Clone)]
+pub struct Pockets {
+	pub fill_below: usize,
+}
+
+impl Regions {
+	pub fn new(size: Size, passable: impl Fn(Coord) -> bool) -> Self {
+		let mut ids = Grid::new_copy(size, None);
+		let mut sizes = Vec::new();
+
+		for start in size.coord_iter_row_major() {
+			if ids.get_checked(start).is_some() || !passable(start) {
+				continue;
+			}
+
+			let id = sizes.len();
+			let mut count = 0;
+			let mut stack = vec![start];
+
+			while let Some(position) = stack.pop() {
+				match ids.get_mut(position) {
+					Some(cell) if cell.is_none() && passable(position) => *cell = Some(id),
+					_ => continue,
+				}
+
+				count += 1;
+
+				for &direction in &Direction::ALL {
+					stack.push(position + direction.offset());
+				}
+			}
+
+			sizes.push(count);
+		}
+
+		Self { ids, sizes }
+	}
+
+	pub fn of_labels(map: &Grid<TileLabel>, tileset: &Tileset) -> Self {
+		Self::new(map.size(), |position| passable(tileset, map.get_checked(position)))
+	}
+
+	pub fn region(&self, position: Coord) -> Option<usize> {
+		self.ids.get(position).copied().flatten()
+	}
+
+	pub fn count(&self) -> usize {
+		self.sizes.len()
+	}
+
+	pub fn size(&self, region: usize) -> usize {
+		self.sizes[region]
+	}
+
+	pub fn largest(&self) -> Option<usize> {
+		(0 .. self.sizes.len()).max_by_key(|&region| self.sizes[region])
+	}
+
+	// The tiles in each region other than the one `from` is in.
+	pub fn pockets(&self, from: Coord) -> Vec<Vec<Coord>> {
+		let main = self.region(from);
+		let mut pockets = vec![Vec::new(); self.sizes.len()];
+
+		for (position, id) in self.ids.enumerate() {
+			match id {
+				Some(id) if Some(*id) != main => pockets[*id].push(position),
+				_ => {},
+			}
+		}
+
+		pockets.retain(|pocket| !pocket.is_empty());
+		pockets
+	}
+
+	// The closest walkable tile to `preferred` in the largest region.
+	pub fn spawn_point(&self, preferred: Coord) -> Option<Coord> {
+		let largest = self.largest()?;
+
+		self.ids.enumerate()
+			.filter(|(_, id)| **id == Some(largest))
+			.map(|(position, _)| position)
+			.min_by_key(|position| position.distance2(preferred))
+	}
+
+	// The blocked tiles on the path from `target` into `region` that goes through the fewest of them.
+	pub fn corridor(&self, target: Coord, region: usize) -> Option<Vec<Coord>> {
+		let mut from: Grid<Option<Coord>> = Grid::new_copy(self.ids.size(), None);
+		let mut cost = Grid::new_copy(self.ids.size(), u32::MAX);
+		let mut queue = VecDeque::new();
+		*cost.get_mut(target)? = 0;
+		queue.push_back(target);
+
+		// Stepping onto a blocked tile costs one, and anything else is free.
+		while let Some(position) = queue.pop_front() {
+			if self.region(position) == Some(region) {
+				let mut path = Vec::new();
+				let mut step = Some(position);
+
+				while let Some(position) = step {
+					if self.region(position).is_none() {
+						path.push(position);
+					}
+					step = *from.get_checked(position);
+				}
+
+				return Some(path);
+			}
+
+			for &direction in &Direction::ALL {
+				let neighbour = position + direction.offset();
+
+				if !neighbour.is_valid(self.ids.size()) {
+					continue;
+				}
+
+				let blocked = self.region(neighbour).is_none() as u32;
+				let next = cost.get_checked(position) + blocked;
+
+				if next < *cost.get_checked(neighbour) {
+					*cost.get_checked_mut(neighbour) = next;
+					*from.get_checked_mut(neighbour) = Some(position);
+
+					if blocked == 0 {
+						queue.push_front(neighbour);
+					} else {
+						queue.push_back(neighbour);
+					}
+				}
+			}
+		}
+
+		None
+	}
+}
+
+// Fills in or joins up everything that can't be reached from `from`, for maps that don't need to
+// follow the tileset's neighbour rules.
+pub fn tidy(map: &mut Grid<TileLabel>, tileset: &Tileset, from: Coord, pockets: Pockets, open: &str, closed: &str) {
+	loop {
+		let regions = Regions::of_labels(map, tileset);
+
+		let main = match regions.region(from) {
+			Some(main) => main,
+			None => return,
+		};
+
+		// Corridors join regions together, so only one is carved before looking again.
+		let mut carved = false;
+
+		for pocket in regions.pockets(from) {
+			let (cells, label) = if pocket.len() < pockets.fill_below {
+				(pocket, closed)
+			} else if !carved {
+				carved = true;
+				(regions.corridor(pocket[0], main).unwrap_or_default(), open)
+			} else {
+				continue;
+			};
+
+			for position in cells {
+				*map.get_checked_mut(position) = TileLabel::new(label);
+			}
+		}
+
+		if !carved {
+			return;
+		}
+	}
+}
+
+// Labels that aren't in the tileset are treated as in the way.
+pub fn passable(tileset: &Tileset, label: &TileLabel) -> bool {
+	let definition = match tileset.tiles.get(&label.label) {
+		Some(definition) => definition,
+		None => return false,
+	};
+
+	let terrain = definition.terrain.as_deref().unwrap_or(&label.label);
+
+	// Whatever a label spawns, such as an egg, is in the way too.
+	definition.spawn.is_none() && TileTag::from_label(terrain).is_some_and(|tag| !tag.blocks_movement())
+}
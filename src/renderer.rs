@@ -6,6 +6,9 @@ use winit::{
 use zerocopy::*;
 use wgpu::util::DeviceExt;
 
+// Must match the size of the `frames` array in `shader.vert`.
+const MAX_ANIMATION_FRAMES: usize = 64;
+
 pub struct Renderer {
 	pub swap_chain: wgpu::SwapChain,
 	pub device: wgpu::Device,
@@ -19,10 +22,23 @@ pub struct Renderer {
 	pub bind_group_layout: wgpu::BindGroupLayout,
 	pub texture: wgpu::TextureView,
 	pub sampler: wgpu::Sampler,
+	pub minimap_pipeline: wgpu::RenderPipeline,
+	pub minimap_bind_group_layout: wgpu::BindGroupLayout,
+	pub minimap: Option<MinimapTexture>,
+	pub uniform_buffer: wgpu::Buffer,
+	pub animation_buffer: wgpu::Buffer,
+	pub terrain_buffer: Option<wgpu::Buffer>,
+	pub start: std::time::Instant,
+}
+
+pub struct MinimapTexture {
+	pub texture: wgpu::Texture,
+	pub bind_group: wgpu::BindGroup,
+	pub size: grid_2d::Size,
 }
 
 impl Renderer {
-	pub async fn new(event_loop: &EventLoop<()>) -> (Self, BufferRenderer) {
+	pub async fn new(event_loop: &EventLoop<()>, tileset: &Tileset) -> (Self, BufferRenderer) {
 		let window = Window::new(event_loop).unwrap();
 
 		let instance = wgpu::Instance::new(wgpu::BackendBit::PRIMARY);
@@ -39,16 +55,24 @@ impl Renderer {
 			features: wgpu::Features::empty(),
 			limits: wgpu::Limits::default(),
 			shader_validation: true,
-		}, Some(&std::path::Path::new("trace"))).await.unwrap();
+		}, Some(std::path::Path::new("trace"))).await.unwrap();
 
 		let vs_module =
 			device.create_shader_module(wgpu::include_spirv!("shader.vert.spv"));
 	
 		let fs_module =
 			device.create_shader_module(wgpu::include_spirv!("shader.frag.spv"));
+
+		let minimap_vs_module =
+			device.create_shader_module(wgpu::include_spirv!("minimap.vert.spv"));
+
+		let minimap_fs_module =
+			device.create_shader_module(wgpu::include_spirv!("minimap.frag.spv"));
 	
-		let mut init_encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
-		let texture = load_png(include_bytes!("alien.png"), &device, &mut init_encoder);
+		let definitions = tileset.atlases();
+		let images = read_atlas_images(&definitions).unwrap_or_else(|err| panic!("{}", err));
+		let atlases = atlas_layouts(&definitions, &images);
+		let texture = load_atlases(&images, &device, &queue);
 		let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
 			address_mode_u: wgpu::AddressMode::Repeat,
 			address_mode_v: wgpu::AddressMode::Repeat,
@@ -63,41 +87,29 @@ impl Renderer {
 			label: None
 		});
 
-		let bind_group_layout =
-			device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-				entries: &[
-					wgpu::BindGroupLayoutEntry {
-						binding: 0,
-						visibility: wgpu::ShaderStage::FRAGMENT,
-						ty: wgpu::BindingType::SampledTexture {
-							multisampled: false,
-							dimension: wgpu::TextureViewDimension::D2,
-							component_type: wgpu::TextureComponentType::Float,
-						},
-						count: None,
-					},
-					wgpu::BindGroupLayoutEntry {
-						binding: 1,
-						visibility: wgpu::ShaderStage::FRAGMENT,
-						ty: wgpu::BindingType::Sampler { comparison: false },
-						count: None,
-					},
-					wgpu::BindGroupLayoutEntry {
-						binding: 2,
-						visibility: wgpu::ShaderStage::VERTEX,
-						ty: wgpu::BindingType::UniformBuffer {
-							dynamic: false,
-							min_binding_size: None,
-						},
-						count: None,
-					}
-				],
-				label: None,
-			});
+		let bind_group_layout = create_bind_group_layout(&device, wgpu::TextureViewDimension::D2Array);
+		let minimap_bind_group_layout = create_bind_group_layout(&device, wgpu::TextureViewDimension::D2);
 
 		let window_size = window.inner_size();
 
-		let bind_group = create_bind_group(&device, &bind_group_layout, &texture, &sampler, Uniforms::new(window_size.width, window_size.height));
+		let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+			label: None,
+			size: std::mem::size_of::<Uniforms>() as wgpu::BufferAddress,
+			usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+			mapped_at_creation: false,
+		});
+
+		let (frames, animations) = tileset.check_layouts(&atlases)
+			.and_then(|()| build_animations(tileset, &atlases))
+			.unwrap_or_else(|err| panic!("{}", err));
+
+		let animation_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+			label: None,
+			contents: frames.as_bytes(),
+			usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+		});
+
+		let bind_group = create_bind_group(&device, &bind_group_layout, &texture, &sampler, &uniform_buffer, &animation_buffer);
 
 		let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
 			bind_group_layouts: &[&bind_group_layout],
@@ -105,60 +117,26 @@ impl Renderer {
 			label: None,
 		});
 
-		let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+		let minimap_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+			bind_group_layouts: &[&minimap_bind_group_layout],
+			push_constant_ranges: Default::default(),
 			label: None,
-			layout: Some(&pipeline_layout),
-			vertex_stage: wgpu::ProgrammableStageDescriptor {
-				module: &vs_module,
-				entry_point: "main",
-			},
-			fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-				module: &fs_module,
-				entry_point: "main",
-			}),
-			rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-				front_face: wgpu::FrontFace::Ccw,
-				cull_mode: wgpu::CullMode::None,
-				depth_bias: 0,
-				depth_bias_slope_scale: 0.0,
-				depth_bias_clamp: 0.0,
-				clamp_depth: false,
-			}),
-			primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-			color_states: &[wgpu::ColorStateDescriptor {
-				format: wgpu::TextureFormat::Bgra8Unorm,
-				color_blend: wgpu::BlendDescriptor {
-					src_factor: wgpu::BlendFactor::SrcAlpha,
-					dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
-					operation: wgpu::BlendOperation::Add,
-				},
-				alpha_blend: wgpu::BlendDescriptor {
-					src_factor: wgpu::BlendFactor::SrcAlpha,
-					dst_factor: wgpu::BlendFactor::DstAlpha,
-					operation: wgpu::BlendOperation::Max,
-				},
-				write_mask: wgpu::ColorWrite::ALL,
-			}],
-			depth_stencil_state: None,
-			vertex_state: wgpu::VertexStateDescriptor {
-				index_format: wgpu::IndexFormat::Uint16,
-				vertex_buffers: &[
-					wgpu::VertexBufferDescriptor {
-						stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
-						step_mode: wgpu::InputStepMode::Vertex,
-						attributes: &wgpu::vertex_attr_array![0 => Float2],
-					},
-					wgpu::VertexBufferDescriptor {
-						stride: std::mem::size_of::<Instance>() as wgpu::BufferAddress,
-						step_mode: wgpu::InputStepMode::Instance,
-						attributes: &wgpu::vertex_attr_array![1 => Float2, 2 => Float2, 3 => Float, 4 => Float2, 5 => Float4],
-					}
-				],
-			},
-			sample_count: 1,
-			sample_mask: !0,
-			alpha_to_coverage_enabled: false,
 		});
+
+		let pipeline = create_pipeline(
+			&device, &pipeline_layout, &vs_module, &fs_module,
+			std::mem::size_of::<Instance>() as wgpu::BufferAddress,
+			&wgpu::vertex_attr_array![
+				1 => Float2, 2 => Float2, 3 => Float, 4 => Float2, 5 => Float4, 6 => Float2, 7 => Float, 8 => Float3, 9 => Float,
+				10 => Float3, 11 => Float2
+			],
+		);
+
+		let minimap_pipeline = create_pipeline(
+			&device, &minimap_pipeline_layout, &minimap_vs_module, &minimap_fs_module,
+			std::mem::size_of::<MinimapInstance>() as wgpu::BufferAddress,
+			&wgpu::vertex_attr_array![1 => Float2, 2 => Float2, 3 => Float4, 4 => Float],
+		);
 	
 		let swap_chain_desc = wgpu::SwapChainDescriptor {
 			usage: wgpu::TextureUsage::OUTPUT_ATTACHMENT,
@@ -170,10 +148,14 @@ impl Renderer {
 	
 		let swap_chain = device.create_swap_chain(&surface, &swap_chain_desc);
 
-		queue.submit(Some(init_encoder.finish()));
-
 		let buffer_renderer = BufferRenderer {
+			atlases,
+			animations,
+			terrain: Vec::new(),
+			terrain_dirty: true,
 			instances: Vec::new(),
+			minimap_instances: Vec::new(),
+			ui_instances: Vec::new(),
 		};
 
 		let square_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -184,7 +166,11 @@ impl Renderer {
 
 		let renderer = Self {
 			square_buffer, swap_chain, pipeline, window, device, queue, swap_chain_desc, surface,
-			bind_group, bind_group_layout, texture, sampler,
+			bind_group, bind_group_layout, texture, sampler, minimap_pipeline, minimap_bind_group_layout,
+			minimap: None,
+			uniform_buffer, animation_buffer,
+			terrain_buffer: None,
+			start: std::time::Instant::now(),
 		};
 
 		(renderer, buffer_renderer)
@@ -194,10 +180,112 @@ impl Renderer {
 		self.swap_chain_desc.width = width;
 		self.swap_chain_desc.height = height;
 		self.swap_chain = self.device.create_swap_chain(&self.surface, &self.swap_chain_desc);
-		self.bind_group = create_bind_group(&self.device, &self.bind_group_layout, &self.texture, &self.sampler, Uniforms::new(width, height));
 	}
 
-	pub fn render(&mut self, renderer: &mut BufferRenderer) {        
+	// Replaces the atlas texture and animations with freshly loaded ones. On failure the old
+	// texture is kept.
+	pub fn reload_atlases(&mut self, tileset: &Tileset, buffers: &mut BufferRenderer) -> Result<(), String> {
+		let definitions = tileset.atlases();
+		let images = read_atlas_images(&definitions)?;
+		let layouts = atlas_layouts(&definitions, &images);
+		tileset.check_layouts(&layouts)?;
+		let (frames, animations) = build_animations(tileset, &layouts)?;
+		let texture = load_atlases(&images, &self.device, &self.queue);
+
+		self.queue.write_buffer(&self.animation_buffer, 0, frames.as_bytes());
+		self.bind_group = create_bind_group(
+			&self.device, &self.bind_group_layout, &texture, &self.sampler, &self.uniform_buffer, &self.animation_buffer,
+		);
+		self.texture = texture;
+		buffers.atlases = layouts;
+		buffers.animations = animations;
+
+		Ok(())
+	}
+
+	fn upload_minimap(&mut self, minimap: &mut Minimap) {
+		if !minimap.dirty {
+			return;
+		}
+
+		let extent = wgpu::Extent3d {
+			width: minimap.size.width(),
+			height: minimap.size.height(),
+			depth: 1,
+		};
+
+		// The map can change size when it is reloaded.
+		let needs_texture = match &self.minimap {
+			Some(texture) => texture.size != minimap.size,
+			None => true,
+		};
+
+		if needs_texture {
+			let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+				size: extent,
+				mip_level_count: 1,
+				sample_count: 1,
+				dimension: wgpu::TextureDimension::D2,
+				format: wgpu::TextureFormat::Rgba8Unorm,
+				usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+				label: None,
+			});
+			let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+			let bind_group = create_bind_group(
+				&self.device, &self.minimap_bind_group_layout, &view, &self.sampler, &self.uniform_buffer, &self.animation_buffer,
+			);
+
+			self.minimap = Some(MinimapTexture { texture, bind_group, size: minimap.size });
+		}
+
+		if let Some(texture) = &self.minimap {
+			self.queue.write_texture(
+				wgpu::TextureCopyView {
+					texture: &texture.texture,
+					mip_level: 0,
+					origin: wgpu::Origin3d::ZERO,
+				},
+				&minimap.pixels,
+				wgpu::TextureDataLayout {
+					offset: 0,
+					bytes_per_row: 4 * minimap.size.width(),
+					rows_per_image: 0,
+				},
+				extent,
+			);
+		}
+
+		minimap.dirty = false;
+	}
+
+	pub fn render(&mut self, renderer: &mut BufferRenderer, minimap: &mut Minimap, camera: &Camera) {
+		self.upload_minimap(minimap);
+
+		let uniforms = Uniforms {
+			window_size: [self.swap_chain_desc.width as f32, self.swap_chain_desc.height as f32],
+			camera_position: camera.position.into(),
+			zoom: camera.zoom,
+			time: self.start.elapsed().as_secs_f32(),
+			_padding: [0.0; 2],
+		};
+		self.queue.write_buffer(&self.uniform_buffer, 0, uniforms.as_bytes());
+
+		// The terrain only changes between turns, so it is kept around between frames.
+		if renderer.terrain_dirty {
+			self.terrain_buffer = if !renderer.terrain.is_empty() {
+				Some(
+					self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+						label: None,
+						contents: renderer.terrain.as_bytes(),
+						usage: wgpu::BufferUsage::VERTEX,
+					})
+				)
+			} else {
+				None
+			};
+			renderer.terrain_dirty = false;
+		}
+
 		let buffers = if !renderer.instances.is_empty() {
 			Some(
 				self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
@@ -210,6 +298,30 @@ impl Renderer {
 			None
 		};
 
+		let minimap_buffers = if !renderer.minimap_instances.is_empty() {
+			Some(
+				self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+					label: None,
+					contents: renderer.minimap_instances.as_bytes(),
+					usage: wgpu::BufferUsage::VERTEX,
+				})
+			)
+		} else {
+			None
+		};
+
+		let ui_buffers = if !renderer.ui_instances.is_empty() {
+			Some(
+				self.device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+					label: None,
+					contents: renderer.ui_instances.as_bytes(),
+					usage: wgpu::BufferUsage::VERTEX,
+				})
+			)
+		} else {
+			None
+		};
+
 		if let Ok(frame) = self.swap_chain.get_current_frame() {
 			let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
 				label: None
@@ -228,13 +340,36 @@ impl Renderer {
 					depth_stencil_attachment: None,
 				});
 	
+				rpass.set_pipeline(&self.pipeline);
+				rpass.set_bind_group(0, &self.bind_group, &[]);
+				rpass.set_vertex_buffer(0, self.square_buffer.slice(..));
+
+				if let Some(terrain) = &self.terrain_buffer {
+					rpass.set_vertex_buffer(1, terrain.slice(..));
+					rpass.draw(0 .. SQUARE.len() as u32, 0 .. renderer.terrain.len() as u32);
+				}
+
 				if let Some(instances) = &buffers {
+					rpass.set_vertex_buffer(1, instances.slice(..));
+					rpass.draw(0 .. SQUARE.len() as u32, 0 .. renderer.instances.len() as u32);
+				}
+
+				if let (Some(instances), Some(minimap)) = (&minimap_buffers, &self.minimap) {
+					rpass.set_pipeline(&self.minimap_pipeline);
+					rpass.set_bind_group(0, &minimap.bind_group, &[]);
+
+					rpass.set_vertex_buffer(0, self.square_buffer.slice(..));
+					rpass.set_vertex_buffer(1, instances.slice(..));
+					rpass.draw(0 .. SQUARE.len() as u32, 0 .. renderer.minimap_instances.len() as u32);
+				}
+
+				if let Some(instances) = &ui_buffers {
 					rpass.set_pipeline(&self.pipeline);
 					rpass.set_bind_group(0, &self.bind_group, &[]);
-	
+
 					rpass.set_vertex_buffer(0, self.square_buffer.slice(..));
 					rpass.set_vertex_buffer(1, instances.slice(..));
-					rpass.draw(0 .. SQUARE.len() as u32, 0 .. renderer.instances.len() as u32);
+					rpass.draw(0 .. SQUARE.len() as u32, 0 .. renderer.ui_instances.len() as u32);
 				}
 			}
 	
@@ -242,6 +377,8 @@ impl Renderer {
 		}
 
 		renderer.instances.clear();
+		renderer.minimap_instances.clear();
+		renderer.ui_instances.clear();
 	}
 
 	pub fn request_redraw(&mut self) {
@@ -249,12 +386,113 @@ impl Renderer {
 	}
 }
 
-fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: &wgpu::TextureView, sampler: &wgpu::Sampler, uniforms: Uniforms) -> wgpu::BindGroup {
-	let buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+fn create_pipeline(
+	device: &wgpu::Device, layout: &wgpu::PipelineLayout,
+	vs_module: &wgpu::ShaderModule, fs_module: &wgpu::ShaderModule,
+	instance_stride: wgpu::BufferAddress, instance_attributes: &[wgpu::VertexAttributeDescriptor],
+) -> wgpu::RenderPipeline {
+	device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
 		label: None,
-		contents: uniforms.as_bytes(),
-		usage: wgpu::BufferUsage::UNIFORM
-	});
+		layout: Some(layout),
+		vertex_stage: wgpu::ProgrammableStageDescriptor {
+			module: vs_module,
+			entry_point: "main",
+		},
+		fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
+			module: fs_module,
+			entry_point: "main",
+		}),
+		rasterization_state: Some(wgpu::RasterizationStateDescriptor {
+			front_face: wgpu::FrontFace::Ccw,
+			cull_mode: wgpu::CullMode::None,
+			depth_bias: 0,
+			depth_bias_slope_scale: 0.0,
+			depth_bias_clamp: 0.0,
+			clamp_depth: false,
+		}),
+		primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+		color_states: &[wgpu::ColorStateDescriptor {
+			format: wgpu::TextureFormat::Bgra8Unorm,
+			color_blend: wgpu::BlendDescriptor {
+				src_factor: wgpu::BlendFactor::SrcAlpha,
+				dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+				operation: wgpu::BlendOperation::Add,
+			},
+			alpha_blend: wgpu::BlendDescriptor {
+				src_factor: wgpu::BlendFactor::SrcAlpha,
+				dst_factor: wgpu::BlendFactor::DstAlpha,
+				operation: wgpu::BlendOperation::Max,
+			},
+			write_mask: wgpu::ColorWrite::ALL,
+		}],
+		depth_stencil_state: None,
+		vertex_state: wgpu::VertexStateDescriptor {
+			index_format: wgpu::IndexFormat::Uint16,
+			vertex_buffers: &[
+				wgpu::VertexBufferDescriptor {
+					stride: std::mem::size_of::<Vertex>() as wgpu::BufferAddress,
+					step_mode: wgpu::InputStepMode::Vertex,
+					attributes: &wgpu::vertex_attr_array![0 => Float2],
+				},
+				wgpu::VertexBufferDescriptor {
+					stride: instance_stride,
+					step_mode: wgpu::InputStepMode::Instance,
+					attributes: instance_attributes,
+				}
+			],
+		},
+		sample_count: 1,
+		sample_mask: !0,
+		alpha_to_coverage_enabled: false,
+	})
+}
+
+fn create_bind_group_layout(device: &wgpu::Device, dimension: wgpu::TextureViewDimension) -> wgpu::BindGroupLayout {
+	device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+		entries: &[
+			wgpu::BindGroupLayoutEntry {
+				binding: 0,
+				visibility: wgpu::ShaderStage::FRAGMENT,
+				ty: wgpu::BindingType::SampledTexture {
+					multisampled: false,
+					dimension,
+					component_type: wgpu::TextureComponentType::Float,
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 1,
+				visibility: wgpu::ShaderStage::FRAGMENT,
+				ty: wgpu::BindingType::Sampler { comparison: false },
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 2,
+				visibility: wgpu::ShaderStage::VERTEX,
+				ty: wgpu::BindingType::UniformBuffer {
+					dynamic: false,
+					min_binding_size: None,
+				},
+				count: None,
+			},
+			wgpu::BindGroupLayoutEntry {
+				binding: 3,
+				visibility: wgpu::ShaderStage::VERTEX,
+				ty: wgpu::BindingType::UniformBuffer {
+					dynamic: false,
+					min_binding_size: None,
+				},
+				count: None,
+			}
+		],
+		label: None,
+	})
+}
+
+fn create_bind_group(
+	device: &wgpu::Device, layout: &wgpu::BindGroupLayout, texture: &wgpu::TextureView, sampler: &wgpu::Sampler,
+	uniforms: &wgpu::Buffer, animations: &wgpu::Buffer,
+) -> wgpu::BindGroup {
 	device.create_bind_group(&wgpu::BindGroupDescriptor {
 		layout,
 		entries: &[
@@ -268,7 +506,11 @@ fn create_bind_group(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, text
 			},
 			wgpu::BindGroupEntry {
 				binding: 2,
-				resource: wgpu::BindingResource::Buffer(buffer.slice(..))
+				resource: wgpu::BindingResource::Buffer(uniforms.slice(..))
+			},
+			wgpu::BindGroupEntry {
+				binding: 3,
+				resource: wgpu::BindingResource::Buffer(animations.slice(..))
 			}
 		],
 		label: None,
@@ -299,68 +541,158 @@ pub struct Instance {
 	pub rotation: f32,
 	pub uv_top_left: [f32; 2],
 	pub overlay: [f32; 4],
+	pub uv_dimensions: [f32; 2],
+	pub atlas: f32,
+	// The index of the first frame, the number of frames and the total length of the animation.
+	pub animation: [f32; 3],
+	pub animation_speed: f32,
+	// Multiplied with the texture, before the overlay is applied.
+	pub tint: [f32; 3],
+	// Scales the quad before it's rotated, to mirror it.
+	pub flip: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(zerocopy::AsBytes, Clone, Debug, Default)]
+pub struct AnimationFrame {
+	pub uv_top_left: [f32; 2],
+	// When the frame starts, in seconds from the start of the animation.
+	pub start: f32,
+	pub _padding: f32,
+	pub overlay: [f32; 4],
 }
 
 #[repr(C)]
 #[derive(zerocopy::AsBytes, Clone, Debug)]
-pub struct Uniforms {
-	window_size: [f32; 2],
+pub struct MinimapInstance {
+	pub center: [f32; 2],
+	pub dimensions: [f32; 2],
+	pub colour: [f32; 4],
+	pub textured: f32,
 }
 
-impl Uniforms {
-	fn new(width: u32, height: u32) -> Self {
-		Self {
-			window_size: [width as f32, height as f32],
-		}
-	}
+#[repr(C)]
+#[derive(zerocopy::AsBytes, Clone, Debug)]
+pub struct Uniforms {
+	window_size: [f32; 2],
+	camera_position: [f32; 2],
+	zoom: f32,
+	time: f32,
+	_padding: [f32; 2],
 }
 
-use crate::Image;
-use crate::Camera;
+use crate::{Camera, Flip};
+use crate::minimap::Minimap;
+use crate::tileset::{Tileset, AtlasDefinition, AtlasLayout, Sprite};
 
 pub struct BufferRenderer {
+	atlases: Vec<AtlasLayout>,
+	animations: Vec<[f32; 3]>,
+	terrain: Vec<Instance>,
+	terrain_dirty: bool,
 	instances: Vec<Instance>,
+	minimap_instances: Vec<MinimapInstance>,
+	// Sprites drawn on top of the minimap's quads, such as the items in the inventory.
+	ui_instances: Vec<Instance>,
 }
 
 impl BufferRenderer {
+	#[allow(clippy::too_many_arguments)]
+	fn instance(
+		&self, tile_position: cgmath::Vector2<f32>, rotation_deg: f32, flip: Flip, sprite: Sprite,
+		overlay: [f32; 4], tint: [f32; 3], animation_speed: f32,
+	) -> Instance {
+		// Sprites are checked against the layouts when the atlases are loaded.
+		let (uv_top_left, uv_dimensions) = self.atlases[sprite.atlas as usize].uv_rect(sprite.coords)
+			.unwrap_or_else(|err| panic!("{}", err));
+
+		Instance {
+			center: tile_position.into(),
+			dimensions: [0.5, 0.5],
+			rotation: rotation_deg.to_radians(),
+			uv_top_left,
+			overlay,
+			uv_dimensions,
+			atlas: sprite.atlas as f32,
+			animation: sprite.animation
+				.map(|animation| self.animations[animation as usize])
+				.unwrap_or_default(),
+			animation_speed,
+			tint,
+			flip: flip.scale(),
+		}
+	}
+
+	// Renders a sprite for the current frame only.
+	#[allow(clippy::too_many_arguments)]
 	pub fn render(
-		&mut self, tile_position: cgmath::Vector2<f32>, rotation_deg: f32, image: &Image,
-		camera: &Camera, overlay: [f32; 4],
+		&mut self, tile_position: cgmath::Vector2<f32>, rotation_deg: f32, flip: Flip, sprite: Sprite,
+		overlay: [f32; 4], tint: [f32; 3], animation_speed: f32,
 	) {
-		let mut position = (camera.position - tile_position) * camera.zoom;
-		position.x = -position.x;
+		let instance = self.instance(tile_position, rotation_deg, flip, sprite, overlay, tint, animation_speed);
+		self.instances.push(instance);
+	}
 
-		self.instances.push(Instance {
-			center: position.into(),
-			dimensions: [0.5 * camera.zoom, 0.5 * camera.zoom],
-			rotation: rotation_deg.to_radians(),
-			uv_top_left: {
-				let (x, y) = image.coords();
-				[x as f32 /  4.0, y as f32 / 4.0]
-			},
-			overlay,
+	// Terrain is rendered once per turn instead of every frame. `clear_terrain` needs to be called
+	// before rendering the new terrain.
+	pub fn render_terrain(
+		&mut self, tile_position: cgmath::Vector2<f32>, rotation_deg: f32, flip: Flip, sprite: Sprite,
+		overlay: [f32; 4], tint: [f32; 3],
+	) {
+		let instance = self.instance(tile_position, rotation_deg, flip, sprite, overlay, tint, 1.0);
+		self.terrain.push(instance);
+	}
+
+	pub fn clear_terrain(&mut self) {
+		self.terrain.clear();
+		self.terrain_dirty = true;
+	}
+
+	// Positioned in half pixels from the middle of the window, like the minimap's quads.
+	pub fn render_ui_sprite(&mut self, center: cgmath::Vector2<f32>, half_size: f32, sprite: Sprite, camera: &Camera) {
+		// Sprites are drawn in tiles relative to the camera, with y pointing down.
+		let position = camera.position + cgmath::vec2(center.x, -center.y) / camera.zoom;
+		let mut instance = self.instance(position, 0.0, Flip::None, sprite, [0.0; 4], [1.0; 3], 1.0);
+		instance.dimensions = [half_size / camera.zoom; 2];
+		self.ui_instances.push(instance);
+	}
+
+	pub fn render_minimap_quad(
+		&mut self, center: cgmath::Vector2<f32>, dimensions: cgmath::Vector2<f32>, colour: [f32; 4], textured: bool,
+	) {
+		self.minimap_instances.push(MinimapInstance {
+			center: center.into(),
+			dimensions: dimensions.into(),
+			colour,
+			textured: if textured { 1.0 } else { 0.0 },
 		});
 	}
 }
 
-fn load_png(bytes: &'static [u8], device: &wgpu::Device, encoder: &mut wgpu::CommandEncoder) -> wgpu::TextureView {
-	let image = image::load_from_memory_with_format(bytes, image::ImageFormat::Png).unwrap()
-		.into_rgba();
-
-	let temp_buf = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-		label: None,
-		contents: &*image,
-		usage: wgpu::BufferUsage::COPY_SRC,
-	});
+fn read_atlas_images(definitions: &[AtlasDefinition]) -> Result<Vec<image::RgbaImage>, String> {
+	definitions.iter()
+		.map(|definition| {
+			let path = definition.image_path();
+			let bytes = std::fs::read(&path)
+				.map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+			let image = image::load_from_memory_with_format(&bytes, image::ImageFormat::Png)
+				.map_err(|err| format!("Failed to decode {}: {}", path.display(), err))?;
+			Ok(image.into_rgba())
+		})
+		.collect()
+}
 
-	let texture_extent = wgpu::Extent3d {
-		width: image.width(),
-		height: image.height(),
-		depth: 1,
-	};
+// Loads every atlas into a layer of a single texture array. Layers are sized to fit the largest
+// atlas, with smaller atlases in the top left corner of their layer.
+fn load_atlases(images: &[image::RgbaImage], device: &wgpu::Device, queue: &wgpu::Queue) -> wgpu::TextureView {
+	let layer_size = layer_size(images);
 
 	let texture = device.create_texture(&wgpu::TextureDescriptor {
-		size: texture_extent,
+		size: wgpu::Extent3d {
+			width: layer_size.0,
+			height: layer_size.1,
+			depth: images.len() as u32,
+		},
 		mip_level_count: 1,
 		sample_count: 1,
 		dimension: wgpu::TextureDimension::D2,
@@ -369,23 +701,82 @@ fn load_png(bytes: &'static [u8], device: &wgpu::Device, encoder: &mut wgpu::Com
 		label: None,
 	});
 
-	encoder.copy_buffer_to_texture(
-		wgpu::BufferCopyView {
-			buffer: &temp_buf,
-			layout: wgpu::TextureDataLayout {
+	// Unlike `copy_buffer_to_texture`, `write_texture` doesn't need rows to be aligned to 256 bytes,
+	// so atlases can be any width.
+	for (layer, image) in images.iter().enumerate() {
+		queue.write_texture(
+			wgpu::TextureCopyView {
+				texture: &texture,
+				mip_level: 0,
+				origin: wgpu::Origin3d { x: 0, y: 0, z: layer as u32 },
+			},
+			image,
+			wgpu::TextureDataLayout {
 				offset: 0,
 				bytes_per_row: 4 * image.width(),
 				rows_per_image: 0,
 			},
-		},
-		wgpu::TextureCopyView {
-			texture: &texture,
-			mip_level: 0,
-			//array_layer: 0,
-			origin: wgpu::Origin3d::ZERO,
-		},
-		texture_extent,
-	);
+			wgpu::Extent3d {
+				width: image.width(),
+				height: image.height(),
+				depth: 1,
+			},
+		);
+	}
+
+	texture.create_view(&wgpu::TextureViewDescriptor {
+		dimension: Some(wgpu::TextureViewDimension::D2Array),
+		..Default::default()
+	})
+}
+
+fn layer_size(images: &[image::RgbaImage]) -> (u32, u32) {
+	(
+		images.iter().map(|image| image.width()).max().unwrap_or(1),
+		images.iter().map(|image| image.height()).max().unwrap_or(1),
+	)
+}
+
+fn atlas_layouts(definitions: &[AtlasDefinition], images: &[image::RgbaImage]) -> Vec<AtlasLayout> {
+	let layer_size = layer_size(images);
+
+	definitions.iter().zip(images)
+		.map(|(definition, image)| AtlasLayout::new(definition, (image.width(), image.height()), layer_size))
+		.collect()
+}
+
+// Flattens the tileset's animations into a fixed size array of frames for the vertex shader,
+// along with the `Instance::animation` value for each animation.
+fn build_animations(tileset: &Tileset, atlases: &[AtlasLayout]) -> Result<(Vec<AnimationFrame>, Vec<[f32; 3]>), String> {
+	let mut frames = Vec::new();
+	let mut animations = Vec::new();
+
+	for (name, animation) in &tileset.animations {
+		let atlas = &atlases[animation.atlas as usize];
+		let first_frame = frames.len();
+		let mut start = 0.0;
+
+		for frame in &animation.frames {
+			frames.push(AnimationFrame {
+				uv_top_left: atlas.uv_rect(frame.coords)?.0,
+				start,
+				_padding: 0.0,
+				overlay: frame.overlay,
+			});
+			start += frame.duration;
+		}
+
+		if frames.len() > MAX_ANIMATION_FRAMES {
+			return Err(format!("Too many animation frames in the tileset, the maximum is {}", MAX_ANIMATION_FRAMES));
+		}
+		if animation.length() <= 0.0 {
+			return Err(format!("Animation '{}' has no length", name));
+		}
+
+		animations.push([first_frame as f32, animation.frames.len() as f32, animation.length()]);
+	}
+
+	frames.resize_with(MAX_ANIMATION_FRAMES, Default::default);
 
-	texture.create_view(&wgpu::TextureViewDescriptor::default())
+	Ok((frames, animations))
 }
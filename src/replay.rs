@@ -0,0 +1,151 @@
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use legion::*;
+use grid_2d::{Coord, Grid};
+use crate::{Tile, Player, Image, Egg, MovementDirection, start_game, add_turn_systems, perform};
+use crate::chunks::Overworld;
+use crate::energy::Energy;
+use crate::items::Fuel;
+use crate::level::Levels;
+use crate::prefabs::Prefabs;
+use crate::rng::GameRng;
+use crate::tileset::{Tileset, ASSET_DIRECTORY};
+use crate::weather::{WorldClock, hash};
+
+// The last new game is recorded here when it's closed.
+pub const REPLAY_PATH: &str = "saves/replay.ron";
+// Replays save the chunks that leave the window here, so they don't touch the real game's.
+pub const CHUNK_DIRECTORY: &str = "saves/replay_chunks";
+
+// Everything the player did in a game, which plays out the same again from its seed and map.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct Replay {
+	pub seed: u64,
+	// Relative to the asset directory, if the map is in it.
+	pub map: PathBuf,
+	// Each command, with the tick of the world clock it was given on.
+	pub commands: Vec<(u64, Command)>,
+	// The world's hash at the end, which playing it back again should come to as well.
+	pub hash: u64,
+}
+
+#[derive(Copy, Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub enum Command {
+	Move(MovementDirection),
+	Rest,
+	Select(usize),
+	PickUp,
+	Drop,
+	Use,
+}
+
+impl Replay {
+	pub fn new(seed: u64, map: &Path) -> Self {
+		let map = map.strip_prefix(ASSET_DIRECTORY).unwrap_or(map).to_path_buf();
+		Self { seed, map, commands: Vec::new(), hash: 0 }
+	}
+
+	pub fn load(path: &Path) -> Self {
+		let bytes = std::fs::read(path)
+			.unwrap_or_else(|err| panic!("Failed to read {}: {}", path.display(), err));
+		ron::de::from_bytes(&bytes).unwrap_or_else(|err| panic!("Failed to parse {}: {}", path.display(), err))
+	}
+
+	pub fn save(&self, path: &Path) {
+		let contents = ron::ser::to_string_pretty(self, Default::default()).unwrap();
+		path.parent().map_or(Ok(()), std::fs::create_dir_all)
+			.and_then(|()| std::fs::write(path, contents))
+			.unwrap_or_else(|err| panic!("Failed to write {}: {}", path.display(), err));
+	}
+}
+
+// Plays `replay` back without a window, saving chunks that leave the window in `chunks`, and
+// returns the world's hash at the end.
+pub fn play(replay: &Replay, assets: &Path, chunks: &Path) -> Result<u64, String> {
+	let mut world = World::default();
+	let mut resources = Resources::default();
+	resources.insert(Tileset::load(&assets.join("alien.ron")));
+	resources.insert(Prefabs::load(&assets.join("prefabs.ron")));
+
+	start_game(&mut world, &mut resources, &assets.join(&replay.map), None, GameRng::new(replay.seed), chunks);
+
+	let mut turn_schedule = add_turn_systems(&mut Schedule::builder()).build();
+	turn_schedule.execute(&mut world, &mut resources);
+
+	for (index, &(ticks, command)) in replay.commands.iter().enumerate() {
+		let now = resources.get::<WorldClock>().unwrap().ticks;
+
+		if now != ticks {
+			return Err(format!("Command {} ({:?}) was given on tick {}, but came up on tick {}", index, command, ticks, now));
+		}
+
+		perform(&mut world, &mut resources, &mut turn_schedule, command);
+	}
+
+	Ok(world_hash(&world, &resources))
+}
+
+// Folds everything that's hashed into the world's hash.
+struct WorldHasher(u64);
+
+impl Hasher for WorldHasher {
+	fn finish(&self) -> u64 {
+		self.0
+	}
+
+	fn write(&mut self, bytes: &[u8]) {
+		for &byte in bytes {
+			self.0 = hash(self.0 ^ byte as u64);
+		}
+	}
+}
+
+// Covers the player, the clock, the level's tiles and everything on them, so that a replay that
+// went differently anywhere the player could tell ends up with a different hash.
+pub fn world_hash(world: &World, resources: &Resources) -> u64 {
+	let mut hasher = WorldHasher(0);
+
+	let player = resources.get::<Player>().unwrap();
+	let overworld = resources.get::<Overworld>().unwrap();
+
+	resources.get::<WorldClock>().unwrap().ticks.hash(&mut hasher);
+	format!("{:?}", resources.get::<Levels>().unwrap().current).hash(&mut hasher);
+	overworld.to_absolute(player.position).hash(&mut hasher);
+	(player.health.current, player.health.max).hash(&mut hasher);
+	(&player.inventory.items, player.inventory.selected).hash(&mut hasher);
+
+	for tile in resources.get::<Grid<Tile>>().unwrap().iter() {
+		(tile.tag.label(), tile.rotation, tile.flip).hash(&mut hasher);
+	}
+
+	// Entities are sorted, as the order they're stored in isn't part of the game.
+	let mut entities: Vec<_> = <(&Coord, Option<&Image>, Option<&Egg>, Option<&Energy>, Option<&Fuel>)>::query().iter(world)
+		.map(|(position, image, egg, energy, fuel)| (
+			(position.x, position.y),
+			image.map(|image| image.0.clone()),
+			egg.map(|egg| egg.0),
+			energy.map(|energy| energy.0),
+			fuel.map(|fuel| fuel.0),
+		))
+		.collect();
+	entities.sort();
+	entities.hash(&mut hasher);
+
+	hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn recorded_replay_ends_the_same() {
+		let assets = Path::new(ASSET_DIRECTORY);
+		let recorded = Replay::load(&assets.join("replays/wow.ron"));
+		let chunks = std::env::temp_dir().join("snowy_replay_test_chunks");
+
+		let hash = play(&recorded, assets, &chunks);
+		let _ = std::fs::remove_dir_all(&chunks);
+		assert_eq!(hash, Ok(recorded.hash));
+	}
+}
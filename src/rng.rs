@@ -0,0 +1,46 @@
+use std::collections::HashMap;
+use crate::weather::{hash, hash_with};
+
+// Every random choice in a run comes from the world's seed, so the run can be played again from it.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct GameRng {
+	pub seed: u64,
+	// Each subsystem draws from its own stream, so one drawing more doesn't change what the others get.
+	streams: HashMap<Stream, Rng>,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, serde::Serialize, serde::Deserialize)]
+pub enum Stream {
+	Hatching,
+}
+
+impl GameRng {
+	pub fn new(seed: u64) -> Self {
+		Self { seed, streams: HashMap::new() }
+	}
+
+	pub fn stream(&mut self, stream: Stream) -> &mut Rng {
+		let seed = self.seed;
+		self.streams.entry(stream).or_insert_with(|| Rng::new(hash_with(seed, stream as u64 + 1)))
+	}
+}
+
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Rng(u64);
+
+impl Rng {
+	pub fn new(seed: u64) -> Self {
+		Self(seed)
+	}
+
+	pub fn next(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(1);
+		hash(self.0)
+	}
+}
+
+// For runs that weren't given a seed.
+pub fn random_seed() -> u64 {
+	let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+	hash(now.as_nanos() as u64)
+}
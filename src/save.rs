@@ -0,0 +1,65 @@
+use std::path::Path;
+use legion::*;
+use grid_2d::{Coord, Grid};
+use crate::{Tile, Visibility, Player};
+use crate::chunks::Overworld;
+use crate::energy::Health;
+use crate::items::Inventory;
+use crate::level;
+use crate::rng::GameRng;
+use crate::weather::WorldClock;
+
+const SAVE_PATH: &str = "saves/game.ron";
+
+// Everything that isn't stored in the overworld's chunks.
+#[derive(serde::Serialize, serde::Deserialize)]
+pub struct SaveGame {
+	// Relative to the authored map, like the starting position.
+	pub position: Coord,
+	pub health: Health,
+	pub inventory: Inventory,
+	pub ticks: u64,
+	// Saves from before there were seeds carry on with a seed of 0, which generates the same world.
+	#[serde(default = "unseeded")]
+	pub rng: GameRng,
+}
+
+fn unseeded() -> GameRng {
+	GameRng::new(0)
+}
+
+pub fn load() -> Option<SaveGame> {
+	if !Path::new(SAVE_PATH).exists() {
+		return None;
+	}
+
+	let bytes = std::fs::read(SAVE_PATH)
+		.unwrap_or_else(|err| panic!("Failed to read {}: {}", SAVE_PATH, err));
+	Some(ron::de::from_bytes(&bytes).unwrap_or_else(|err| panic!("Failed to parse {}: {}", SAVE_PATH, err)))
+}
+
+// The player is brought back up to the surface first, and the caves they've been in are saved
+// alongside the overworld's chunks.
+pub fn save(world: &mut World, resources: &mut Resources) {
+	level::leave_cave(world, resources);
+	level::save_caves(resources);
+
+	let overworld = resources.get::<Overworld>().unwrap();
+	let player = resources.get::<Player>().unwrap();
+
+	overworld.save_window(
+		world, &resources.get::<Grid<Tile>>().unwrap(), &resources.get::<Grid<Visibility>>().unwrap(),
+	);
+
+	let save = SaveGame {
+		position: overworld.to_absolute(player.position),
+		health: player.health.clone(),
+		inventory: player.inventory.clone(),
+		ticks: resources.get::<WorldClock>().unwrap().ticks,
+		rng: resources.get::<GameRng>().unwrap().clone(),
+	};
+
+	let contents = ron::ser::to_string(&save).unwrap();
+	std::fs::write(SAVE_PATH, contents)
+		.unwrap_or_else(|err| panic!("Failed to write {}: {}", SAVE_PATH, err));
+}
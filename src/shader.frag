@@ -3,12 +3,16 @@
 layout(location = 0) out vec4 outColor;
 layout(location = 0) in vec2 out_uv;
 layout(location = 1) in vec4 out_overlay;
+layout(location = 2) in float out_atlas;
+layout(location = 3) in vec3 out_tint;
 
-layout(set = 0, binding = 0) uniform texture2D tex;
+layout(set = 0, binding = 0) uniform texture2DArray tex;
 layout(set = 0, binding = 1) uniform sampler samp;
 
 void main() {
-    vec4 texture_colour = texture(sampler2D(tex, samp), out_uv);
+    vec4 texture_colour = texture(sampler2DArray(tex, samp), vec3(out_uv, out_atlas));
 
-    outColor = vec4(mix(texture_colour.rgb, out_overlay.rgb, out_overlay.a), texture_colour.a);
+    vec3 lit_colour = texture_colour.rgb * out_tint;
+
+    outColor = vec4(mix(lit_colour, out_overlay.rgb, out_overlay.a), texture_colour.a);
 }
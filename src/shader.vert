@@ -7,30 +7,70 @@ layout(location = 2) in vec2 i_dimensions;
 layout(location = 3) in float i_rotation;
 layout(location = 4) in vec2 i_uv_top_left;
 layout(location = 5) in vec4 i_overlay;
+layout(location = 6) in vec2 i_uv_dimensions;
+layout(location = 7) in float i_atlas;
+layout(location = 8) in vec3 i_animation;
+layout(location = 9) in float i_animation_speed;
+layout(location = 10) in vec3 i_tint;
+layout(location = 11) in vec2 i_flip;
 
 layout(location = 0) out vec2 out_uv;
 layout(location = 1) out vec4 out_overlay;
+layout(location = 2) out float out_atlas;
+layout(location = 3) out vec3 out_tint;
 
 layout(set = 0, binding = 2) uniform Uniforms {
     vec2 window_size;
+    vec2 camera_position;
+    float zoom;
+    float time;
 };
 
-void main() {
-    //vec2 pos = i_center * vec2(1.0, -1.0);
+struct Frame {
+    vec2 uv_top_left;
+    float start;
+    vec4 overlay;
+};
 
+layout(set = 0, binding = 3) uniform Animations {
+    Frame frames[64];
+};
+
+void main() {
     mat2 rotation = mat2(
         cos(i_rotation), -sin(i_rotation),
         sin(i_rotation),  cos(i_rotation)
     );
 
-    vec2 tiles_pos = i_center + (rotation * (i_dimensions * v_point));
+    vec2 center = (i_center - camera_position) * vec2(1.0, -1.0) * zoom;
+
+    vec2 tiles_pos = center + (rotation * (i_flip * i_dimensions * zoom * v_point));
 
     gl_Position = vec4(tiles_pos / window_size, 0.0, 1.0);
 
-    vec2 uv_dimensions = vec2(1.0 / 4.0, 1.0 / 4.0);
+    vec2 uv_top_left = i_uv_top_left;
+    vec4 overlay = i_overlay;
+
+    int frame_count = int(i_animation.y);
+
+    if (frame_count > 0) {
+        int first_frame = int(i_animation.x);
+        float animation_time = mod(time * i_animation_speed, i_animation.z);
+
+        for (int i = 0; i < frame_count; i++) {
+            Frame frame = frames[first_frame + i];
+
+            if (frame.start <= animation_time) {
+                uv_top_left = frame.uv_top_left;
+                overlay = vec4(mix(frame.overlay.rgb, i_overlay.rgb, i_overlay.a), max(frame.overlay.a, i_overlay.a));
+            }
+        }
+    }
 
-    vec2 uv_offset = i_uv_top_left + uv_dimensions * 0.5;
+    vec2 uv_offset = uv_top_left + i_uv_dimensions * 0.5;
 
-    out_overlay = i_overlay;
-    out_uv = uv_offset + uv_dimensions * vec2(1.0, -1.0) * v_point * 0.5;
+    out_overlay = overlay;
+    out_uv = uv_offset + i_uv_dimensions * vec2(1.0, -1.0) * v_point * 0.5;
+    out_atlas = i_atlas;
+    out_tint = i_tint;
 }
@@ -0,0 +1,525 @@
+use std::collections::{HashMap, BTreeMap};
+use std::path::{Path, PathBuf};
+use grid_2d::Coord;
+use crate::{TileLabel, Rotation, Flip};
+
+// Assets are loaded from this directory at runtime, which is found from where the crate was built
+// so that the game can be started from anywhere.
+pub const ASSET_DIRECTORY: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+
+#[derive(serde::Deserialize)]
+pub struct Tileset {
+	pub tileset_image_path: String,
+	pub tile_size: u32,
+	#[serde(default)]
+	pub columns: Option<u32>,
+	#[serde(default)]
+	pub rows: Option<u32>,
+	// Additional atlases, referenced by tiles with `atlas: 1` onwards.
+	#[serde(default)]
+	pub extra_atlases: Vec<AtlasDefinition>,
+	pub tiles: HashMap<String, TileDefinition>,
+	// Sprites for entities, which aren't part of the terrain.
+	#[serde(default)]
+	pub sprites: HashMap<String, SpriteDefinition>,
+	#[serde(default)]
+	pub animations: BTreeMap<String, AnimationDefinition>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AtlasDefinition {
+	pub image_path: String,
+	pub tile_size: u32,
+	#[serde(default)]
+	pub columns: Option<u32>,
+	#[serde(default)]
+	pub rows: Option<u32>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct TileDefinition {
+	// How often the tile is picked when generating maps, relative to the others.
+	#[serde(default = "default_weight")]
+	pub weight: u32,
+	pub coords: (u32, u32),
+	#[serde(default)]
+	pub atlas: u32,
+	#[serde(default)]
+	pub animation: Option<String>,
+	#[serde(default)]
+	pub properties: TileProperties,
+	// A prefab to spawn on the tile, for labels that place entities.
+	#[serde(default)]
+	pub spawn: Option<String>,
+	// The tile that's actually placed, if it's different to the label.
+	#[serde(default)]
+	pub terrain: Option<String>,
+	#[serde(default)]
+	pub rotatable: Rotatable,
+	#[serde(default)]
+	pub flippable: Flippable,
+	// Which tiles may be placed next to this one, for the tile in its normal rotation.
+	#[serde(default)]
+	pub allowed_neighbours: HashMap<NeighbourDirection, Vec<TileLabel>>,
+}
+
+fn default_weight() -> u32 {
+	1
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug, serde::Deserialize)]
+pub enum NeighbourDirection {
+	All,
+	Up,
+	Right,
+	Down,
+	Left,
+}
+
+// In clockwise order, so that turning by a `Rotation` is addition.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Direction {
+	Up,
+	Right,
+	Down,
+	Left,
+}
+
+impl Direction {
+	pub const ALL: [Self; 4] = [Self::Up, Self::Right, Self::Down, Self::Left];
+
+	pub fn offset(self) -> Coord {
+		match self {
+			Self::Up => Coord::new(0, -1),
+			Self::Right => Coord::new(1, 0),
+			Self::Down => Coord::new(0, 1),
+			Self::Left => Coord::new(-1, 0),
+		}
+	}
+
+	pub fn opposite(self) -> Self {
+		self.turn(2)
+	}
+
+	fn turn(self, quarter_turns: u8) -> Self {
+		Self::ALL[(self as usize + quarter_turns as usize) % 4]
+	}
+
+	fn rule(self) -> NeighbourDirection {
+		match self {
+			Self::Up => NeighbourDirection::Up,
+			Self::Right => NeighbourDirection::Right,
+			Self::Down => NeighbourDirection::Down,
+			Self::Left => NeighbourDirection::Left,
+		}
+	}
+}
+
+#[derive(Default, serde::Deserialize)]
+pub enum Rotatable {
+	#[default]
+	No,
+	Yes { symmetry: Symmetry },
+}
+
+// Rotations of a tile that look the same are treated as the same tile.
+#[derive(Copy, Clone, serde::Deserialize)]
+pub enum Symmetry {
+	None,
+	// Looks the same when turned upside down.
+	Half,
+	Full,
+}
+
+// Tiles that can't be flipped have rules that ignore flips, as with rotations.
+#[derive(Default, serde::Deserialize)]
+pub enum Flippable {
+	#[default]
+	No,
+	Yes { axis: Option<Axis> },
+}
+
+// A line that a tile can be mirrored across and still look the same.
+#[derive(Copy, Clone, serde::Deserialize)]
+pub enum Axis {
+	// Swaps left and right.
+	Vertical,
+	// Swaps top and bottom.
+	Horizontal,
+	// Swaps the top and left sides.
+	Diagonal,
+	// Swaps the top and right sides.
+	AntiDiagonal,
+}
+
+// One of the eight ways of placing a tile: mirrored left to right or not, then turned clockwise.
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Transform {
+	mirrored: bool,
+	turns: u8,
+}
+
+impl Transform {
+	const IDENTITY: Self = Self { mirrored: false, turns: 0 };
+
+	pub fn all() -> impl Iterator<Item = Self> {
+		[false, true].iter().flat_map(|&mirrored| (0 .. 4).map(move |turns| Self { mirrored, turns }))
+	}
+
+	pub fn of(label: &TileLabel) -> Self {
+		let turns = quarter_turns(label.rotation);
+
+		match label.flip {
+			Flip::None => Self { mirrored: false, turns },
+			Flip::Horizontal => Self { mirrored: true, turns },
+			// Mirroring top to bottom is the same as mirroring left to right and turning half way.
+			Flip::Vertical => Self { mirrored: true, turns: (turns + 2) % 4 },
+		}
+	}
+
+	// The transform that does `first`, then `self`.
+	fn after(self, first: Self) -> Self {
+		// Mirroring reverses the direction of any turns made before it.
+		let turns = if self.mirrored { 4 - first.turns } else { first.turns };
+		Self { mirrored: self.mirrored != first.mirrored, turns: (self.turns + turns) % 4 }
+	}
+
+	fn inverse(self) -> Self {
+		match self.mirrored {
+			true => self,
+			false => Self { mirrored: false, turns: (4 - self.turns) % 4 },
+		}
+	}
+
+	fn apply(self, direction: Direction) -> Direction {
+		let direction = match (self.mirrored, direction) {
+			(true, Direction::Left) => Direction::Right,
+			(true, Direction::Right) => Direction::Left,
+			(_, direction) => direction,
+		};
+		direction.turn(self.turns)
+	}
+
+	// The label, placed with this transform.
+	pub fn label(self, label: &TileLabel) -> TileLabel {
+		TileLabel {
+			rotation: from_quarter_turns(self.turns),
+			flip: if self.mirrored { Flip::Horizontal } else { Flip::None },
+			..label.clone()
+		}
+	}
+}
+
+impl Axis {
+	fn mirror(self) -> Transform {
+		let turns = match self {
+			Axis::Vertical => 0,
+			Axis::AntiDiagonal => 1,
+			Axis::Horizontal => 2,
+			Axis::Diagonal => 3,
+		};
+		Transform { mirrored: true, turns }
+	}
+}
+
+// Clockwise quarter turns.
+fn quarter_turns(rotation: Rotation) -> u8 {
+	match rotation {
+		Rotation::Normal => 0,
+		Rotation::Plus90 => 1,
+		Rotation::Opposite => 2,
+		Rotation::Minus90 => 3,
+	}
+}
+
+fn from_quarter_turns(turns: u8) -> Rotation {
+	[Rotation::Normal, Rotation::Plus90, Rotation::Opposite, Rotation::Minus90][turns as usize % 4]
+}
+
+// How a tile affects things moving across it.
+#[derive(Copy, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct TileProperties {
+	// Energy taken to move onto the tile.
+	pub movement_cost: u32,
+	// Extra energy taken to move off the tile.
+	pub stickiness: u32,
+	// Moving onto a slippery tile carries you on to the next tile in the same direction.
+	pub slippery: bool,
+	// How many tiles away moving onto the tile can be heard from.
+	pub noise: i32,
+}
+
+impl Default for TileProperties {
+	fn default() -> Self {
+		Self {
+			movement_cost: crate::energy::ACTION_COST,
+			stickiness: 0,
+			slippery: false,
+			noise: 2,
+		}
+	}
+}
+
+#[derive(serde::Deserialize)]
+pub struct SpriteDefinition {
+	pub coords: (u32, u32),
+	#[serde(default)]
+	pub atlas: u32,
+	#[serde(default)]
+	pub animation: Option<String>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct AnimationDefinition {
+	// Should match the atlas of the sprites that use the animation.
+	#[serde(default)]
+	pub atlas: u32,
+	pub frames: Vec<FrameDefinition>,
+}
+
+#[derive(serde::Deserialize)]
+pub struct FrameDefinition {
+	pub coords: (u32, u32),
+	// In seconds.
+	pub duration: f32,
+	// Blended over the frame, like the visibility overlay.
+	#[serde(default)]
+	pub overlay: [f32; 4],
+}
+
+impl AnimationDefinition {
+	pub fn length(&self) -> f32 {
+		self.frames.iter().map(|frame| frame.duration).sum()
+	}
+}
+
+impl Tileset {
+	pub fn load(path: &Path) -> Self {
+		Self::try_load(path).unwrap_or_else(|err| panic!("{}", err))
+	}
+
+	pub fn try_load(path: &Path) -> Result<Self, String> {
+		let bytes = std::fs::read(path)
+			.map_err(|err| format!("Failed to read {}: {}", path.display(), err))?;
+		let tileset: Self = ron::de::from_bytes(&bytes)
+			.map_err(|err| format!("Failed to parse {}: {}", path.display(), err))?;
+		tileset.check().map_err(|err| format!("{} in {}", err, path.display()))?;
+		Ok(tileset)
+	}
+
+	// Checks that every atlas and animation that's referred to exists, so that looking up sprites
+	// can't fail later on.
+	fn check(&self) -> Result<(), String> {
+		let atlases = self.atlases().len() as u32;
+
+		for (atlas, coords, animation) in self.sprite_definitions() {
+			if atlas >= atlases {
+				return Err(format!("There is no atlas {}", atlas));
+			}
+			self.sprite(atlas, coords, animation)?;
+		}
+
+		for (name, animation) in &self.animations {
+			if animation.atlas >= atlases {
+				return Err(format!("Animation '{}' is in atlas {}, which doesn't exist", name, animation.atlas));
+			}
+			if animation.length() <= 0.0 {
+				return Err(format!("Animation '{}' has no length", name));
+			}
+		}
+
+		Ok(())
+	}
+
+	// Checks that every sprite fits inside its atlas, once the sizes of the atlas images are known.
+	pub fn check_layouts(&self, layouts: &[AtlasLayout]) -> Result<(), String> {
+		for (atlas, coords, _) in self.sprite_definitions() {
+			layouts[atlas as usize].uv_rect(coords)?;
+		}
+
+		for animation in self.animations.values() {
+			for frame in &animation.frames {
+				layouts[animation.atlas as usize].uv_rect(frame.coords)?;
+			}
+		}
+
+		Ok(())
+	}
+
+	// The atlas, coordinates and animation of every tile and entity sprite.
+	fn sprite_definitions(&self) -> impl Iterator<Item = (u32, (u32, u32), &Option<String>)> {
+		self.tiles.values().map(|tile| (tile.atlas, tile.coords, &tile.animation))
+			.chain(self.sprites.values().map(|sprite| (sprite.atlas, sprite.coords, &sprite.animation)))
+	}
+
+	pub fn atlases(&self) -> Vec<AtlasDefinition> {
+		let primary = AtlasDefinition {
+			image_path: self.tileset_image_path.clone(),
+			tile_size: self.tile_size,
+			columns: self.columns,
+			rows: self.rows,
+		};
+
+		std::iter::once(primary)
+			.chain(self.extra_atlases.iter().map(|atlas| AtlasDefinition {
+				image_path: atlas.image_path.clone(),
+				..*atlas
+			}))
+			.collect()
+	}
+
+	pub fn tile_sprite(&self, label: &str) -> Sprite {
+		let tile = self.tiles.get(label)
+			.unwrap_or_else(|| panic!("Tileset does not contain '{}'", label));
+		// The tileset was checked when it was loaded.
+		self.sprite(tile.atlas, tile.coords, &tile.animation).unwrap_or_else(|err| panic!("{}", err))
+	}
+
+	pub fn tile_properties(&self, label: &str) -> TileProperties {
+		self.tiles.get(label)
+			.unwrap_or_else(|| panic!("Tileset does not contain '{}'", label))
+			.properties
+	}
+
+	// The transforms that leave a tile looking the same.
+	fn symmetries(definition: &TileDefinition) -> Vec<Transform> {
+		let rotation = match definition.rotatable {
+			Rotatable::Yes { symmetry: Symmetry::None } => None,
+			Rotatable::Yes { symmetry: Symmetry::Half } => Some(Transform { mirrored: false, turns: 2 }),
+			_ => Some(Transform { mirrored: false, turns: 1 }),
+		};
+
+		let mirror = match definition.flippable {
+			Flippable::Yes { axis } => axis.map(Axis::mirror),
+			Flippable::No => Some(Transform { mirrored: true, turns: 0 }),
+		};
+
+		let generators: Vec<Transform> = rotation.into_iter().chain(mirror).collect();
+		let mut symmetries = vec![Transform::IDENTITY];
+		let mut index = 0;
+
+		while let Some(&symmetry) = symmetries.get(index) {
+			for generator in &generators {
+				let next = generator.after(symmetry);
+
+				if !symmetries.contains(&next) {
+					symmetries.push(next);
+				}
+			}
+			index += 1;
+		}
+
+		symmetries
+	}
+
+	// The transform that a label's rotation and flip are equivalent to, given its symmetry.
+	fn canonical_transform(&self, label: &TileLabel) -> Transform {
+		let transform = Transform::of(label);
+
+		match self.tiles.get(&label.label) {
+			Some(definition) => Self::symmetries(definition).into_iter()
+				.map(|symmetry| transform.after(symmetry))
+				.min()
+				.unwrap_or(transform),
+			None => transform,
+		}
+	}
+
+	// The label without any turns or flips that don't change how the tile looks.
+	pub fn canonical(&self, label: &TileLabel) -> TileLabel {
+		self.canonical_transform(label).label(label)
+	}
+
+	pub fn same_tile(&self, a: &TileLabel, b: &TileLabel) -> bool {
+		a.label == b.label && self.canonical_transform(a) == self.canonical_transform(b)
+	}
+
+	// The tiles that `tile` allows next to it in the given direction, turned and flipped to match it.
+	pub fn allowed_neighbours(&self, tile: &TileLabel, direction: Direction) -> Vec<TileLabel> {
+		let definition = match self.tiles.get(&tile.label) {
+			Some(definition) => definition,
+			None => return Vec::new(),
+		};
+
+		let transform = self.canonical_transform(tile);
+		// The direction as the tile sees it, before it was transformed.
+		let local = transform.inverse().apply(direction);
+
+		[NeighbourDirection::All, local.rule()].iter()
+			.filter_map(|rule| definition.allowed_neighbours.get(rule))
+			.flatten()
+			.map(|neighbour| transform.after(Transform::of(neighbour)).label(neighbour))
+			.collect()
+	}
+
+	pub fn allows(&self, tile: &TileLabel, direction: Direction, neighbour: &TileLabel) -> bool {
+		self.allowed_neighbours(tile, direction).iter().any(|allowed| self.same_tile(allowed, neighbour))
+	}
+
+	pub fn entity_sprite(&self, label: &str) -> Sprite {
+		let sprite = self.sprites.get(label)
+			.unwrap_or_else(|| panic!("Tileset does not contain a sprite for '{}'", label));
+		self.sprite(sprite.atlas, sprite.coords, &sprite.animation).unwrap_or_else(|err| panic!("{}", err))
+	}
+
+	fn sprite(&self, atlas: u32, coords: (u32, u32), animation: &Option<String>) -> Result<Sprite, String> {
+		let animation = match animation {
+			Some(name) => Some(
+				self.animations.keys().position(|key| key == name)
+					.ok_or_else(|| format!("Tileset does not contain an animation named '{}'", name))? as u32
+			),
+			None => None,
+		};
+
+		Ok(Sprite { atlas, coords, animation })
+	}
+}
+
+impl AtlasDefinition {
+	pub fn image_path(&self) -> PathBuf {
+		Path::new(ASSET_DIRECTORY).join(&self.image_path)
+	}
+}
+
+// A single cell in one of the atlases, optionally animated.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Sprite {
+	pub atlas: u32,
+	pub coords: (u32, u32),
+	// Index into the tileset's animations.
+	pub animation: Option<u32>,
+}
+
+// Where an atlas' tiles are in the texture array, which is sized to fit the largest atlas.
+pub struct AtlasLayout {
+	pub tile_size: u32,
+	pub columns: u32,
+	pub rows: u32,
+	pub layer_size: (u32, u32),
+}
+
+impl AtlasLayout {
+	pub fn new(definition: &AtlasDefinition, image_size: (u32, u32), layer_size: (u32, u32)) -> Self {
+		let tile_size = definition.tile_size;
+
+		Self {
+			tile_size,
+			columns: definition.columns.unwrap_or(image_size.0 / tile_size),
+			rows: definition.rows.unwrap_or(image_size.1 / tile_size),
+			layer_size,
+		}
+	}
+
+	// Returns the top left corner and dimensions of the sprite in uv space.
+	pub fn uv_rect(&self, (x, y): (u32, u32)) -> Result<([f32; 2], [f32; 2]), String> {
+		if x >= self.columns || y >= self.rows {
+			return Err(format!("Sprite ({}, {}) is outside of a {}x{} atlas", x, y, self.columns, self.rows));
+		}
+
+		let width = self.tile_size as f32 / self.layer_size.0 as f32;
+		let height = self.tile_size as f32 / self.layer_size.1 as f32;
+
+		Ok(([x as f32 * width, y as f32 * height], [width, height]))
+	}
+}
@@ -0,0 +1,108 @@
+use std::time::{Duration, Instant};
+use grid_2d::Coord;
+use legion::*;
+use legion::systems::CommandBuffer;
+use crate::Rotation;
+
+pub struct TweenSettings {
+	pub enabled: bool,
+	pub duration: Duration,
+}
+
+impl Default for TweenSettings {
+	fn default() -> Self {
+		Self {
+			enabled: true,
+			duration: Duration::from_millis(120),
+		}
+	}
+}
+
+// Where a sprite is drawn, which lags behind its logical `Coord` while it animates from one tile to
+// the next. Systems only ever look at the `Coord`.
+pub struct Tween {
+	from: cgmath::Vector2<f32>,
+	from_rotation: f32,
+	to: Coord,
+	to_rotation: f32,
+	started: Instant,
+}
+
+impl Tween {
+	pub fn new(coord: Coord, rotation_deg: f32) -> Self {
+		Self {
+			from: to_vec(coord),
+			from_rotation: rotation_deg,
+			to: coord,
+			to_rotation: rotation_deg,
+			started: Instant::now(),
+		}
+	}
+
+	// Used when the coordinates of the whole map are shifted, so nothing appears to move.
+	pub fn translate(&mut self, offset: Coord) {
+		self.from += to_vec(offset);
+		self.to += offset;
+	}
+
+	// Returns the position and rotation to draw the sprite at, starting a new tween if the
+	// sprite has moved or rotated since the last call.
+	pub fn update(&mut self, coord: Coord, rotation_deg: f32, settings: &TweenSettings) -> (cgmath::Vector2<f32>, f32) {
+		if coord != self.to || rotation_deg != self.to_rotation {
+			// Anything that moves further than a single step has been teleported, so it snaps
+			// to its new position.
+			let (from, from_rotation) = if (coord - self.to).distance2(Coord::new(0, 0)) > 2 {
+				(to_vec(coord), rotation_deg)
+			} else {
+				self.current(settings)
+			};
+
+			*self = Self {
+				from, from_rotation,
+				to: coord,
+				to_rotation: rotation_deg,
+				started: Instant::now(),
+			};
+		}
+
+		self.current(settings)
+	}
+
+	fn current(&self, settings: &TweenSettings) -> (cgmath::Vector2<f32>, f32) {
+		let progress = if settings.enabled && settings.duration > Duration::from_secs(0) {
+			let t = (self.started.elapsed().as_secs_f32() / settings.duration.as_secs_f32()).min(1.0);
+			// Ease in and out.
+			t * t * (3.0 - 2.0 * t)
+		} else {
+			1.0
+		};
+
+		let position = self.from + (to_vec(self.to) - self.from) * progress;
+
+		// Rotate the shortest way around.
+		let mut delta = (self.to_rotation - self.from_rotation) % 360.0;
+		if delta > 180.0 {
+			delta -= 360.0;
+		} else if delta < -180.0 {
+			delta += 360.0;
+		}
+
+		(position, self.from_rotation + delta * progress)
+	}
+}
+
+fn to_vec(coord: Coord) -> cgmath::Vector2<f32> {
+	cgmath::vec2(coord.x as f32, coord.y as f32)
+}
+
+pub struct PlayerTween(pub Tween);
+
+#[legion::system(for_each)]
+#[filter(!component::<Tween>())]
+pub fn attach_tweens(
+	entity: &Entity, position: &Coord, rotation: Option<&Rotation>,
+	buffer: &mut CommandBuffer,
+) {
+	let rotation = rotation.map(|rotation| rotation.to_deg()).unwrap_or(0.0);
+	buffer.add_component(*entity, Tween::new(*position, rotation));
+}
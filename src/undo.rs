@@ -0,0 +1,119 @@
+use std::collections::VecDeque;
+use legion::*;
+use legion::world::Duplicate;
+use grid_2d::{Coord, Grid};
+use crate::{Tile, Visibility, Player, Image, Egg, BlocksMovement, Rotation};
+use crate::chunks::Overworld;
+use crate::energy::{Speed, Energy, Noise};
+use crate::items::{Item, Fuel};
+use crate::level::{Levels, LevelId};
+use crate::light::LightSource;
+use crate::minimap::Minimap;
+use crate::prefabs::Prefab;
+use crate::rng::GameRng;
+use crate::weather::{WorldClock, Sky};
+
+// How many turns can be stepped back through.
+const CAPACITY: usize = 50;
+
+// Everything a turn can change, as it was before the turn was taken.
+pub struct Snapshot {
+	world: World,
+	player: Player,
+	map: Grid<Tile>,
+	vis_map: Grid<Visibility>,
+	clock: WorldClock,
+	rng: GameRng,
+	noise: Noise,
+	// Turns can't be undone across levels or chunk boundaries, as the rest of the world is
+	// stored elsewhere by then.
+	level: LevelId,
+	origin: Coord,
+}
+
+impl Snapshot {
+	pub fn take(world: &World, resources: &Resources) -> Self {
+		// Tweens are left out, so entities snap straight back to where they were. Any other
+		// component that entities in a level can have must be registered here, or undoing a turn
+		// drops it.
+		let mut duplicate = Duplicate::default();
+		duplicate.register_copy::<Coord>();
+		duplicate.register_copy::<Rotation>();
+		duplicate.register_copy::<BlocksMovement>();
+		duplicate.register_copy::<Egg>();
+		duplicate.register_copy::<LightSource>();
+		duplicate.register_copy::<Speed>();
+		duplicate.register_copy::<Energy>();
+		duplicate.register_copy::<Item>();
+		duplicate.register_copy::<Fuel>();
+		duplicate.register_clone::<Image>();
+		duplicate.register_clone::<Prefab>();
+
+		let mut copy = World::default();
+		copy.clone_from(world, &any(), &mut duplicate);
+
+		Self {
+			world: copy,
+			player: resources.get::<Player>().unwrap().clone(),
+			map: resources.get::<Grid<Tile>>().unwrap().clone(),
+			vis_map: resources.get::<Grid<Visibility>>().unwrap().clone(),
+			clock: *resources.get::<WorldClock>().unwrap(),
+			rng: resources.get::<GameRng>().unwrap().clone(),
+			noise: *resources.get::<Noise>().unwrap(),
+			level: resources.get::<Levels>().unwrap().current,
+			origin: resources.get::<Overworld>().unwrap().origin,
+		}
+	}
+}
+
+// The last few turns, for stepping back through while testing.
+#[derive(Default)]
+pub struct History {
+	snapshots: VecDeque<Snapshot>,
+}
+
+impl History {
+	// Keeps `snapshot` if a turn has passed since it was taken. Moving to another level or chunk
+	// forgets everything before it.
+	pub fn push(&mut self, snapshot: Snapshot, resources: &Resources) {
+		if snapshot.clock.ticks == resources.get::<WorldClock>().unwrap().ticks {
+			return;
+		}
+
+		if snapshot.level != resources.get::<Levels>().unwrap().current
+			|| snapshot.origin != resources.get::<Overworld>().unwrap().origin
+		{
+			self.snapshots.clear();
+			return;
+		}
+
+		if self.snapshots.len() == CAPACITY {
+			self.snapshots.pop_front();
+		}
+
+		self.snapshots.push_back(snapshot);
+	}
+
+	pub fn clear(&mut self) {
+		self.snapshots.clear();
+	}
+
+	// Puts the world back how it was before the last turn, returning whether there was one to undo.
+	pub fn undo(&mut self, world: &mut World, resources: &mut Resources) -> bool {
+		let snapshot = match self.snapshots.pop_back() {
+			Some(snapshot) => snapshot,
+			None => return false,
+		};
+
+		*world = snapshot.world;
+		resources.insert(Sky::new(&snapshot.clock, snapshot.rng.seed));
+		resources.insert(snapshot.clock);
+		resources.insert(snapshot.player);
+		resources.insert(snapshot.map);
+		resources.insert(snapshot.vis_map);
+		resources.insert(snapshot.rng);
+		resources.insert(snapshot.noise);
+		resources.get_mut::<Minimap>().unwrap().dirty = true;
+		true
+	}
+}
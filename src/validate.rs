@@ -0,0 +1,105 @@
+use std::fmt;
+use grid_2d::{Coord, Grid};
+use crate::{TileLabel, Rotation, Flip};
+use crate::tileset::{Tileset, Direction};
+
+// A pair of neighbouring tiles that neither tile's `allowed_neighbours` permits.
+pub struct Violation {
+	pub position: Coord,
+	pub tile: TileLabel,
+	pub direction: Direction,
+	pub neighbour: TileLabel,
+	// What the tile would allow in the neighbour's place.
+	pub expected: Vec<TileLabel>,
+}
+
+impl fmt::Display for Violation {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		let expected: Vec<String> = self.expected.iter().map(describe).collect();
+
+		write!(
+			f, "({}, {}): {} has {} to the {:?}, expected one of [{}]",
+			self.position.x, self.position.y, describe(&self.tile), describe(&self.neighbour),
+			self.direction, expected.join(", "),
+		)
+	}
+}
+
+fn describe(label: &TileLabel) -> String {
+	match (label.rotation, label.flip) {
+		(Rotation::Normal, Flip::None) => label.label.clone(),
+		(rotation, Flip::None) => format!("{} ({:?})", label.label, rotation),
+		(rotation, flip) => format!("{} ({:?}, {:?} flip)", label.label, rotation, flip),
+	}
+}
+
+// Checks every pair of neighbouring tiles in the map. Rules are written for tiles in their normal
+// rotation, and turn and flip with the tile.
+pub fn validate(map: &Grid<TileLabel>, tileset: &Tileset) -> Vec<Violation> {
+	let mut violations = Vec::new();
+
+	for (position, tile) in map.enumerate() {
+		for &direction in &[Direction::Right, Direction::Down] {
+			let neighbour = match map.get(position + direction.offset()) {
+				Some(neighbour) => neighbour,
+				None => continue,
+			};
+
+			let allowed = tileset.allows(tile, direction, neighbour)
+				|| tileset.allows(neighbour, direction.opposite(), tile);
+
+			if !allowed {
+				// Only the ways of placing each tile that look different are listed.
+				let mut expected: Vec<TileLabel> = tileset.allowed_neighbours(tile, direction).iter()
+					.map(|label| tileset.canonical(label))
+					.collect();
+				expected.sort();
+				expected.dedup();
+
+				violations.push(Violation {
+					position,
+					tile: tile.clone(),
+					direction,
+					neighbour: neighbour.clone(),
+					expected,
+				});
+			}
+		}
+	}
+
+	violations
+}
+
+#[cfg(test)]
+mod tests {
+	use std::path::Path;
+	use grid_2d::Size;
+	use super::*;
+	use crate::map::load_map;
+
+	fn tileset() -> Tileset {
+		Tileset::load(&Path::new(crate::tileset::ASSET_DIRECTORY).join("alien.ron"))
+	}
+
+	#[test]
+	fn shipped_map_is_valid() {
+		let map = load_map(&Path::new(crate::tileset::ASSET_DIRECTORY).join("wow.ron")).unwrap();
+		let violations = validate(&map.terrain, &tileset());
+
+		assert!(violations.is_empty(), "{}", violations.iter().map(Violation::to_string).collect::<Vec<_>>().join("\n"));
+	}
+
+	#[test]
+	fn water_next_to_ground_is_a_violation() {
+		let tileset = tileset();
+		let labels = [TileLabel::new("ground"), TileLabel::new("water")];
+		let map = Grid::new_fn(Size::new(2, 1), |position| labels[position.x as usize].clone());
+
+		let violations = validate(&map, &tileset);
+
+		assert_eq!(violations.len(), 1);
+		assert_eq!(violations[0].position, Coord::new(0, 0));
+		assert_eq!(violations[0].direction, Direction::Right);
+		assert!(violations[0].expected.iter().all(|label| tileset.canonical(label) == *label));
+	}
+}
@@ -0,0 +1,185 @@
+use grid_2d::{Coord, Grid};
+use crate::{Tile, TileTag, Player};
+use crate::tileset::Tileset;
+use crate::energy::{Elapsed, ACTION_COST, periods_elapsed};
+use crate::rng::GameRng;
+
+const TICKS_PER_DAY: u64 = 400 * ACTION_COST as u64;
+// How long the weather lasts before it can change.
+const TICKS_PER_WEATHER: u64 = 150 * ACTION_COST as u64;
+// How often a blizzard hurts the player when they're out in it.
+const TICKS_PER_FROSTBITE: u64 = 3 * ACTION_COST as u64;
+
+#[derive(Copy, Clone, Default)]
+pub struct WorldClock {
+	pub ticks: u64,
+}
+
+impl WorldClock {
+	// From 0.0 at midnight to 0.5 at midday.
+	pub fn time_of_day(&self) -> f32 {
+		(self.ticks % TICKS_PER_DAY) as f32 / TICKS_PER_DAY as f32
+	}
+
+	// 1.0 during the day and 0.0 at night, with dusk and dawn in between.
+	pub fn daylight(&self) -> f32 {
+		let time = self.time_of_day();
+		let height = -(time * std::f32::consts::PI * 2.0).cos();
+		(height * 2.0 + 0.5).clamp(0.0, 1.0)
+	}
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Weather {
+	Clear,
+	Snowfall,
+	Blizzard,
+}
+
+impl Weather {
+	fn for_ticks(ticks: u64, seed: u64) -> Self {
+		match hash_with(seed, ticks / TICKS_PER_WEATHER) % 20 {
+			0 ..= 9 => Self::Clear,
+			10 ..= 16 => Self::Snowfall,
+			_ => Self::Blizzard,
+		}
+	}
+
+	// The chance every `ACTION_COST` ticks of a patch of ground being covered in snow.
+	fn snow_chance(&self) -> f32 {
+		match self {
+			Self::Clear => 0.0,
+			Self::Snowfall => 0.002,
+			Self::Blizzard => 0.01,
+		}
+	}
+
+	fn overlay(&self) -> [f32; 4] {
+		match self {
+			Self::Clear => [0.0; 4],
+			Self::Snowfall => [0.9, 0.92, 1.0, 0.08],
+			Self::Blizzard => [0.9, 0.92, 1.0, 0.3],
+		}
+	}
+}
+
+// A cheap hash for deterministic variation.
+pub fn hash(value: u64) -> u64 {
+	let mut value = value.wrapping_add(0x9E37_79B9_7F4A_7C15);
+	value = (value ^ (value >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+	value = (value ^ (value >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+	value ^ (value >> 31)
+}
+
+// Hashes `value` under `seed`, so each seed gives its own unrelated values.
+pub fn hash_with(seed: u64, value: u64) -> u64 {
+	hash(seed ^ hash(value))
+}
+
+// How the time of day and weather affect what the player sees. Recalculated every turn.
+pub struct Sky {
+	pub weather: Weather,
+	pub sight_radius: i32,
+	// Multiplied with tiles that aren't dark.
+	pub ambient: [f32; 3],
+	// Blended over visible tiles.
+	pub overlay: [f32; 4],
+}
+
+impl Sky {
+	pub fn new(clock: &WorldClock, seed: u64) -> Self {
+		let weather = Weather::for_ticks(clock.ticks, seed);
+		let daylight = clock.daylight();
+
+		let night = [0.3, 0.35, 0.6];
+		let dusk = [1.0, 0.7, 0.55];
+		let day = [1.0; 3];
+
+		// Fade from night through dusk to day.
+		let ambient = if daylight < 0.5 {
+			lerp(night, dusk, daylight * 2.0)
+		} else {
+			lerp(dusk, day, daylight * 2.0 - 1.0)
+		};
+
+		let weather_penalty = match weather {
+			Weather::Clear => 0,
+			Weather::Snowfall => 2,
+			Weather::Blizzard => 5,
+		};
+
+		let sight_radius = 5 + (daylight * (crate::SIGHT_RADIUS - 5) as f32).round() as i32 - weather_penalty;
+
+		Self {
+			weather,
+			sight_radius: sight_radius.max(2),
+			ambient,
+			overlay: weather.overlay(),
+		}
+	}
+}
+
+fn lerp(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+	[a[0] + (b[0] - a[0]) * t, a[1] + (b[1] - a[1]) * t, a[2] + (b[2] - a[2]) * t]
+}
+
+#[legion::system]
+pub fn advance_clock(
+	#[resource] clock: &mut WorldClock,
+	#[resource] sky: &mut Sky,
+	#[resource] rng: &GameRng,
+	#[resource] elapsed: &Elapsed,
+) {
+	clock.ticks += elapsed.0 as u64;
+	*sky = Sky::new(clock, rng.seed);
+}
+
+// Gradually covers bare ground in snow while it's snowing.
+#[legion::system]
+pub fn snowfall(
+	#[resource] clock: &WorldClock,
+	#[resource] sky: &Sky,
+	#[resource] map: &mut Grid<Tile>,
+	#[resource] tileset: &Tileset,
+	#[resource] rng: &GameRng,
+	#[resource] elapsed: &Elapsed,
+) {
+	let chance = sky.weather.snow_chance() * elapsed.0 as f32 / ACTION_COST as f32;
+
+	if chance == 0.0 {
+		return;
+	}
+
+	let threshold = (chance * u32::MAX as f32) as u64;
+
+	for (Coord { x, y }, tile) in map.enumerate_mut() {
+		if !matches!(tile.tag, TileTag::Ground) {
+			continue;
+		}
+
+		let roll = hash_with(hash_with(rng.seed, clock.ticks), ((x as u64) << 32) | y as u32 as u64) & u32::MAX as u64;
+
+		if roll < threshold {
+			tile.tag = TileTag::SnowyGround;
+			tile.sprite = tileset.tile_sprite(tile.tag.label());
+			tile.properties = tileset.tile_properties(tile.tag.label());
+		}
+	}
+}
+
+// Blizzards hurt the player unless they shelter in a cave.
+#[legion::system]
+pub fn exposure(
+	#[resource] clock: &WorldClock,
+	#[resource] sky: &Sky,
+	#[resource] map: &Grid<Tile>,
+	#[resource] player: &mut Player,
+	#[resource] elapsed: &Elapsed,
+) {
+	if sky.weather != Weather::Blizzard || map.get_checked(player.position).tag.is_dark() {
+		return;
+	}
+
+	let damage = periods_elapsed(clock, elapsed, TICKS_PER_FROSTBITE);
+	player.health.current = player.health.current.saturating_sub(damage);
+}